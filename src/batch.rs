@@ -0,0 +1,418 @@
+mod html;
+
+use crate::{
+    halftone, output, output::Output, BatchConfig, Builder, Canvas, Error, Render, Result,
+    ShapeList,
+};
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Extensions of the files picked up from an input directory
+const IMAGE_EXTENSIONS: [&str; 9] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp", "ppm",
+];
+
+/// What happened to one image
+enum Outcome {
+    Built {
+        shapes: usize,
+        elapsed: Duration,
+        // mean per-channel error (0-255) of the built image
+        error: f32,
+    },
+    // every output was newer than the image; the shape count is read back
+    // from the raw output
+    UpToDate {
+        shapes: Option<usize>,
+    },
+    Failed(Error),
+}
+
+/// Builds every image in a directory or matching a glob, several at a time
+pub struct Batch {
+    config: BatchConfig,
+    output: Output,
+}
+
+impl Batch {
+    pub fn new(config: BatchConfig) -> Self {
+        Self {
+            config,
+            output: Output::default(),
+        }
+    }
+
+    /// Where progress and the result are reported
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Builds the images, then prints a table of how each went. Fails with
+    /// the first image's error if any of them failed, after the table.
+    pub fn run(self) -> Result<()> {
+        let images = self.images()?;
+        if images.is_empty() {
+            return Err(Error::Config(format!(
+                "no images found in {}",
+                self.config.input
+            )));
+        }
+
+        self.check_outputs_differ(&images)?;
+
+        std::fs::create_dir_all(&self.config.output_dir)
+            .map_err(|e| Error::io(&self.config.output_dir, e))?;
+
+        // 0 threads leaves it to rayon: one per CPU
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.config.jobs.unwrap_or(0))
+            .build()
+            .map_err(|e| Error::Config(e.to_string()))?;
+        let outcomes: Vec<Outcome> =
+            pool.install(|| images.par_iter().map(|image| self.process(image)).collect());
+
+        if self.output.is_json() {
+            self.emit_result(&images, &outcomes);
+        } else {
+            self.print_summary(&images, &outcomes);
+        }
+
+        match outcomes.into_iter().find_map(|o| match o {
+            Outcome::Failed(e) => Some(e),
+            _ => None,
+        }) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Image files in the input directory, or matching the input pattern,
+    /// in name order
+    fn images(&self) -> Result<Vec<PathBuf>> {
+        let input = &self.config.input;
+
+        let mut images: Vec<PathBuf> = if Path::new(input).is_dir() {
+            std::fs::read_dir(input)
+                .map_err(|e| Error::io(input, e))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| {
+                    path.extension()
+                        .map(|e| e.to_string_lossy().to_lowercase())
+                        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.as_str()))
+                })
+                .collect()
+        } else {
+            glob::glob(input)
+                .map_err(|e| Error::Config(format!("{}: {}", input, e)))?
+                .filter_map(|path| path.ok())
+                .filter(|path| path.is_file())
+                .collect()
+        };
+
+        images.sort();
+        Ok(images)
+    }
+
+    /// The output names that were asked for, raw first
+    fn templates(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.config.raw).chain(
+            [&self.config.svg, &self.config.html, &self.config.png]
+                .into_iter()
+                .flatten(),
+        )
+    }
+
+    fn output_path(&self, template: &str, image: &Path) -> PathBuf {
+        let ext = image
+            .extension()
+            .map(|e| e.to_string_lossy())
+            .unwrap_or_default();
+        let name = template
+            .replace("{stem}", &Self::stem(image))
+            .replace("{ext}", &ext);
+        Path::new(&self.config.output_dir).join(name)
+    }
+
+    /// Fails if two images would write the same output, as images that differ
+    /// only by extension do with names made from {stem} alone
+    fn check_outputs_differ(&self, images: &[PathBuf]) -> Result<()> {
+        let mut writers: HashMap<PathBuf, &Path> = HashMap::new();
+
+        for image in images {
+            for template in self.templates() {
+                let path = self.output_path(template, image);
+                if let Some(other) = writers.insert(path.clone(), image) {
+                    return Err(Error::Config(format!(
+                        "{} and {} would both write {}; tell them apart with {{stem}} and {{ext}} in the output names",
+                        other.display(),
+                        image.display(),
+                        path.display()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process(&self, image: &Path) -> Outcome {
+        let raw = self.output_path(&self.config.raw, image);
+
+        if !self.config.force && self.is_up_to_date(image) {
+            let shapes = ShapeList::read(&raw.to_string_lossy())
+                .ok()
+                .map(|s| s.circles.len());
+            return Outcome::UpToDate { shapes };
+        }
+
+        self.output
+            .status(format!("Building {} ...", image.display()));
+        match self.build(image) {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome::Failed(e),
+        }
+    }
+
+    // true if every output exists and was written after the image last changed
+    fn is_up_to_date(&self, image: &Path) -> bool {
+        let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+        let Some(image_modified) = modified(image) else {
+            return false;
+        };
+
+        self.templates().all(|template| {
+            modified(&self.output_path(template, image)).is_some_and(|m| m >= image_modified)
+        })
+    }
+
+    fn build(&self, image: &Path) -> Result<Outcome> {
+        let start = Instant::now();
+        let reference = Canvas::open(&image.to_string_lossy())?;
+        let channels = (reference.width() as f32) * (reference.height() as f32) * 3.0;
+
+        let mut builder = Builder::new(reference, self.config.options.clone())?;
+        let shapes = builder.run()?;
+
+        let path = |template: &str| {
+            self.output_path(template, image)
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        shapes.write(&path(&self.config.raw))?;
+
+        if let Some(template) = &self.config.png {
+            builder.image().save(&path(template))?;
+        }
+
+        if self.config.svg.is_some() || self.config.html.is_some() {
+            let svg = Self::render_svg(&shapes);
+
+            if let Some(template) = &self.config.svg {
+                let path = path(template);
+                std::fs::write(&path, &svg).map_err(|e| Error::io(&path, e))?;
+            }
+
+            if let Some(template) = &self.config.html {
+                let path = path(template);
+                let page = html::render_html(&svg, &Self::stem(image), shapes.circles.len());
+                std::fs::write(&path, page).map_err(|e| Error::io(&path, e))?;
+            }
+        }
+
+        Ok(Outcome::Built {
+            shapes: shapes.circles.len(),
+            elapsed: start.elapsed(),
+            error: builder.stats().delta as f32 / channels,
+        })
+    }
+
+    // shapes are written as built; `sediment render` prunes them
+    fn render_svg(shapes: &ShapeList) -> String {
+        match shapes.split_plates() {
+            Some(plates) => {
+                let inks = halftone::PLATES.map(|p| p.ink);
+                Render::render_separations_svg(
+                    &plates,
+                    &inks,
+                    Render::image_width(&shapes.circles),
+                    Render::image_height(&shapes.circles),
+                )
+            }
+            None => Render::render_svg(&shapes.circles, shapes.background()),
+        }
+    }
+
+    fn stem(image: &Path) -> String {
+        image
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn emit_result(&self, images: &[PathBuf], outcomes: &[Outcome]) {
+        let images: Vec<serde_json::Value> = images
+            .iter()
+            .zip(outcomes)
+            .map(|(image, outcome)| {
+                let outputs: Vec<String> = self
+                    .templates()
+                    .map(|t| self.output_path(t, image).to_string_lossy().into_owned())
+                    .collect();
+
+                let mut fields = match outcome {
+                    Outcome::Built {
+                        shapes,
+                        elapsed,
+                        error,
+                    } => json!({
+                        "status": "built",
+                        "shapes": shapes,
+                        "seconds": elapsed.as_secs_f64(),
+                        "error": output::value(error),
+                        "outputs": outputs,
+                    }),
+                    Outcome::UpToDate { shapes } => json!({
+                        "status": "up-to-date",
+                        "shapes": shapes,
+                        "outputs": outputs,
+                    }),
+                    Outcome::Failed(e) => json!({
+                        "status": "failed",
+                        "message": e.to_string(),
+                        "outputs": [],
+                    }),
+                };
+                fields["image"] = json!(image.to_string_lossy());
+                fields
+            })
+            .collect();
+
+        self.output.emit("result", json!({ "images": images }));
+    }
+
+    fn print_summary(&self, images: &[PathBuf], outcomes: &[Outcome]) {
+        let names: Vec<String> = images
+            .iter()
+            .map(|i| {
+                i.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        let width = names.iter().map(|n| n.len()).max().unwrap_or(0).max(5);
+
+        println!(
+            "{:<width$}  {:>8}  {:>8}  {:>7}  status",
+            "image", "shapes", "time", "error"
+        );
+        for (name, outcome) in names.iter().zip(outcomes) {
+            match outcome {
+                Outcome::Built {
+                    shapes,
+                    elapsed,
+                    error,
+                } => println!(
+                    "{:<width$}  {:>8}  {:>7.1}s  {:>7.3}  built",
+                    name,
+                    shapes,
+                    elapsed.as_secs_f32(),
+                    error
+                ),
+                Outcome::UpToDate { shapes } => println!(
+                    "{:<width$}  {:>8}  {:>8}  {:>7}  up to date",
+                    name,
+                    shapes.map_or("-".to_owned(), |s| s.to_string()),
+                    "-",
+                    "-"
+                ),
+                Outcome::Failed(e) => {
+                    println!(
+                        "{:<width$}  {:>8}  {:>8}  {:>7}  failed: {}",
+                        name, "-", "-", "-", e
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scratch::Scratch, BuildOptions, Circle};
+    use image::Rgba;
+
+    fn batch(dir: &Path, raw: &str, svg: Option<&str>) -> Batch {
+        Batch::new(BatchConfig {
+            input: dir.to_string_lossy().into_owned(),
+            output_dir: dir.join("out").to_string_lossy().into_owned(),
+            raw: raw.to_owned(),
+            svg: svg.map(str::to_owned),
+            html: None,
+            png: None,
+            jobs: Some(1),
+            force: false,
+            preset: None,
+            config: None,
+            options: BuildOptions {
+                max_radius: 10,
+                min_radius: 4,
+                ..Default::default()
+            },
+        })
+    }
+
+    #[test]
+    fn skips_images_that_are_up_to_date() {
+        let scratch = Scratch::new("batch");
+        let dir = scratch.path();
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+
+        let mut canvas = Canvas::new(32, 24);
+        canvas.draw_circle(&Circle::new(16, 12, 8, Rgba([255, 255, 255, 255])));
+        canvas.save(dir.join("dot.png").to_str().unwrap()).unwrap();
+
+        let batch = batch(dir, "{stem}.smt", Some("{stem}.svg"));
+        let image = batch.images().unwrap().remove(0);
+        assert!(matches!(batch.process(&image), Outcome::Built { .. }));
+        assert!(dir.join("out/dot.svg").is_file());
+        assert!(matches!(
+            batch.process(&image),
+            Outcome::UpToDate { shapes: Some(_) }
+        ));
+    }
+
+    #[test]
+    fn refuses_images_that_would_share_outputs() {
+        let scratch = Scratch::new("batch-clash");
+        let dir = scratch.path();
+        std::fs::create_dir_all(dir).unwrap();
+
+        let canvas = Canvas::new(8, 8);
+        for name in ["dot.png", "dot.bmp"] {
+            canvas.save(dir.join(name).to_str().unwrap()).unwrap();
+        }
+
+        let clashing = batch(dir, "{stem}.smt", None);
+        let images = clashing.images().unwrap();
+        assert!(matches!(
+            clashing.check_outputs_differ(&images),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(clashing.run(), Err(Error::Config(_))));
+
+        // the extension tells them apart
+        let apart = batch(dir, "{stem}-{ext}.smt", Some("{stem}.{ext}.svg"));
+        assert!(apart.check_outputs_differ(&images).is_ok());
+
+        // and one name for every image never does
+        let shared = batch(dir, "{stem}-{ext}.smt", Some("all.svg"));
+        assert!(shared.check_outputs_differ(&images).is_err());
+    }
+}
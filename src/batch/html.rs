@@ -0,0 +1,90 @@
+/// Page around a rendered SVG that reveals its circles in build order, large
+/// ones a few at a time and small ones in ever bigger batches. The same page
+/// as pages/template.html.erb, without the site's header and credits.
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8" />
+    <title>{title}</title>
+    <style>
+      body {
+        margin: 0;
+        padding: 0;
+        background-color: #000;
+      }
+      circle {
+        display: none;
+      }
+      #info {
+        background-color: #456da8;
+        color: #fff;
+        font-family: sans-serif;
+        padding: 5px;
+        position: fixed;
+        top: 0;
+        width: 100%;
+      }
+    </style>
+    <script>
+      function sleep(ms) {
+        return new Promise((resolve) => setTimeout(resolve, ms));
+      }
+      async function render() {
+        var svg = document.getElementById("sedimentSvg");
+        var progress = document.getElementById("progress");
+        var counter = document.getElementById("counter");
+        var circles = svg.getElementsByTagName("circle");
+        var count = circles.length;
+        var pause_at = 0;
+        var max_radius = 0;
+        var delay = 25; // ms between rendering sets
+
+        for (var idx = 0; idx < count; idx++) {
+          var circle = circles[idx];
+          circle.style.display = "block";
+
+          if (idx == 0) {
+            // sets our denominator for determining the number of circles to show before we pause
+            max_radius = circle.getAttribute("r");
+          }
+
+          if (idx == pause_at) {
+            var radius = circle.getAttribute("r");
+            var show_count = Math.round(max_radius / radius);
+            pause_at += show_count;
+            await sleep(delay);
+          }
+
+          progress.innerHTML = Math.round(((idx + 1) / count) * 100);
+          counter.innerHTML = idx + 1;
+        }
+      }
+    </script>
+  </head>
+  <body>
+    <div id="info">
+      <b>{title}</b> - {shape_count} Dots (<span id="counter">0</span>,
+      <span id="progress">0</span>%)
+    </div>
+
+{svg}
+
+    <script>
+      render();
+    </script>
+  </body>
+</html>
+"#;
+
+/// Wraps an SVG from `Render::render_svg` in an animated page
+pub(super) fn render_html(svg: &str, title: &str, shape_count: usize) -> String {
+    let title = title
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    TEMPLATE
+        .replace("{title}", &title)
+        .replace("{shape_count}", &shape_count.to_string())
+        .replace("{svg}", svg)
+}
@@ -1,20 +1,23 @@
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::channel;
+use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::{
-    point_selector::RandomPointSelector, rate_meter::RateMeter, BuildConfig, Canvas, Circle, Region,
+    error_map::ErrorMap,
+    halftone, mosaic,
+    observer::{BuilderUpdate, Channel, NoOp, Observer},
+    palette::Palette,
+    point_selector::{RandomPointSelector, ScreenPointSelector},
+    pyramid::Pyramid,
+    rate_meter::RateMeter,
+    shape_list::ShapeList,
+    spatial_index::{SpatialIndex, DEFAULT_CELL_SIZE},
+    BuildMode, BuildOptions, Canvas, Circle, Error, Region, Result,
 };
-use image::{GenericImage, Rgba};
+use image::Rgba;
 
-pub enum BuilderUpdate {
-    Preview(image::DynamicImage),
-    Stats(Stats),
-}
-
-pub enum BuilderCommand {
-    Start,
-    Quit,
-}
+/// How far around a stipple dot its tone is judged, as a multiple of its radius
+const STIPPLE_WINDOW: u32 = 4;
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Stats {
@@ -35,47 +38,159 @@ pub struct Stats {
     pub elapsed: Duration,
 }
 
+/// Outcome of testing a candidate circle against a reference/current pair
+enum Attempt {
+    // the region already looks good enough; don't bother
+    Skip,
+    // the candidate doesn't improve the region
+    Reject,
+    // the candidate improves the region
+    Accept,
+}
+
 pub struct Builder {
     reference: Canvas,
     current: Canvas,
-    config: BuildConfig,
-    tx: Sender<BuilderUpdate>,
+    errors: ErrorMap,
+    pyramid: Pyramid,
+    color_picker: ColorPicker,
+    // set when the build doesn't start from the usual black canvas
+    background: Option<Rgba<u8>>,
+    // placed circles, indexed for the overlap test when packing
+    packing: Option<SpatialIndex>,
+    // when set, shapes are only centered on the cells of a halftone screen
+    // with this pitch and angle
+    screen: Option<(u32, f32)>,
+    config: BuildOptions,
+    // hears about the build as it goes
+    observer: Box<dyn Observer>,
+    // how often the observer hears about progress, if at all
+    progress_interval: Option<Duration>,
     circles: Vec<Circle>,
+    // in CMYK mode, the plate each circle belongs to
+    plates: Vec<usize>,
     stats: Stats,
-    last_update: Instant,
+    started: Instant,
+    last_progress: Instant,
 }
 
 impl Builder {
-    pub fn new(tx: Sender<BuilderUpdate>, config: BuildConfig) -> Self {
-        let reference = Canvas::open(&config.input).unwrap();
+    /// Fails if the options are invalid, or name a palette or color that
+    /// can't be parsed
+    pub fn new(reference: Canvas, config: BuildOptions) -> Result<Self> {
+        config.validate()?;
+
         let width = reference.width();
         let height = reference.height();
 
-        Self {
+        // stippling puts a single ink on white paper; everything else starts
+        // from black with whatever colors the palette (if any) allows
+        let (background, palette) = match config.mode {
+            BuildMode::Stipple => {
+                let ink = Palette::parse_hex(&config.ink).map_err(Error::Config)?;
+                (
+                    Some(Rgba([255, 255, 255, 255])),
+                    Some(Palette::new(vec![ink])),
+                )
+            }
+            BuildMode::Cmyk => (Some(Rgba([255, 255, 255, 255])), Some(halftone::palette())),
+            BuildMode::Standard => {
+                let palette = config
+                    .palette
+                    .as_ref()
+                    .map(|spec| Palette::parse(spec, &reference))
+                    .transpose()?;
+                (None, palette)
+            }
+            BuildMode::Pack | BuildMode::Grid => {
+                let background = Palette::parse_hex(&config.background).map_err(Error::Config)?;
+                let palette = config
+                    .palette
+                    .as_ref()
+                    .map(|spec| Palette::parse(spec, &reference))
+                    .transpose()?;
+                (Some(background), palette)
+            }
+        };
+        let packing = (config.mode == BuildMode::Pack)
+            .then(|| SpatialIndex::new(width, height, DEFAULT_CELL_SIZE));
+
+        let current = match background {
+            Some(color) => Canvas::filled(width, height, color),
+            None => Canvas::new(width, height),
+        };
+        let errors = ErrorMap::new(&reference, &current);
+        let pyramid = Pyramid::new(&reference, &current, config.pyramid_levels);
+        let stats = Stats {
+            delta: errors.total(),
+            ..Default::default()
+        };
+
+        Ok(Self {
             reference,
-            current: Canvas::new(width, height),
+            current,
+            errors,
+            pyramid,
+            color_picker: ColorPicker { palette },
+            background,
+            packing,
+            screen: None,
             config,
-            tx,
+            observer: Box::new(NoOp),
+            progress_interval: None,
             circles: vec![],
-            stats: Stats::default(),
-            last_update: Instant::now(),
-        }
+            plates: vec![],
+            stats,
+            started: Instant::now(),
+            last_progress: Instant::now(),
+        })
+    }
+
+    /// Reports the build's events to the given observer
+    pub fn with_observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observer = Box::new(observer);
+        self
+    }
+
+    /// Has the observer hear about progress at most once per `interval`.
+    /// Without this, it only hears about individual events.
+    pub fn with_progress(mut self, interval: Duration) -> Self {
+        self.progress_interval = Some(interval);
+        self
+    }
+
+    // centers shapes on the cells of a halftone screen only
+    fn with_screen(mut self, pitch: u32, angle: f32) -> Self {
+        self.screen = Some((pitch, angle));
+        self
+    }
+
+    /// The image as built so far
+    pub fn image(&self) -> &Canvas {
+        &self.current
     }
 
-    pub fn send_updates(&mut self) {
-        self.tx
-            .send(BuilderUpdate::Preview(self.current.img.clone()))
-            .unwrap();
+    /// The image being approximated
+    pub fn reference(&self) -> &Canvas {
+        &self.reference
+    }
 
-        self.tx.send(BuilderUpdate::Stats(self.stats)).unwrap();
+    /// How the build has gone so far
+    pub fn stats(&self) -> Stats {
+        self.stats
     }
 
-    pub fn update_ui(&mut self) {
-        // update ten times per second
-        if self.last_update.elapsed() > Duration::from_millis(100) {
-            self.last_update = Instant::now();
-            self.send_updates();
+    fn report_progress(&mut self) -> Result<()> {
+        let Some(interval) = self.progress_interval else {
+            return Ok(());
+        };
+
+        if self.last_progress.elapsed() > interval {
+            self.last_progress = Instant::now();
+            self.stats.elapsed = self.started.elapsed();
+            self.observer.on_progress(&self.stats, &self.current)?;
         }
+        Ok(())
     }
 
     fn radius_step_down(&self) -> u32 {
@@ -87,24 +202,32 @@ impl Builder {
         int_step
     }
 
-    pub fn interactive(&mut self, rx: Receiver<BuilderCommand>) {
-        loop {
-            match rx.recv().unwrap() {
-                BuilderCommand::Start => {
-                    self.run();
-                }
-                BuilderCommand::Quit => {
-                    return;
-                }
+    pub fn run(&mut self) -> Result<ShapeList> {
+        self.started = Instant::now();
+
+        // halftones are built a plate at a time, and mosaics are laid out in one
+        // pass rather than searched for
+        if matches!(self.config.mode, BuildMode::Cmyk | BuildMode::Grid) {
+            if self.config.mode == BuildMode::Cmyk {
+                self.run_separations()?;
+            } else {
+                self.run_mosaic()?;
             }
-        }
-    }
+            self.stats.elapsed = self.started.elapsed();
 
-    pub fn run(&mut self) {
-        let start_time = Instant::now();
+            for circle in &self.circles {
+                self.observer.on_shape_committed(circle, &self.stats)?;
+            }
+            return self.finish();
+        }
 
         // generates points to examine for shape placement
-        let mut point_selector = RandomPointSelector::new(&self.reference);
+        let mut point_selector: Box<dyn Iterator<Item = (u32, u32)>> = match self.screen {
+            Some((pitch, angle)) => {
+                Box::new(ScreenPointSelector::new(&self.reference, pitch, angle))
+            }
+            None => Box::new(RandomPointSelector::new(&self.reference)),
+        };
 
         // tracks the success rate for the current radius
         let mut radius_success_rate = RateMeter::new(100);
@@ -118,21 +241,22 @@ impl Builder {
             self.stats.total_attempts += 1;
             self.stats.radius_attempts += 1;
 
+            // progress is timed by attempts rather than placed shapes, so a
+            // radius that places nothing still gets reported
+            self.report_progress()?;
+
             // examine the success rate to determine if we need to adjust our radius
             if radius_success_rate.is_below(self.config.radius_shrink_threshold)
                 || (self.stats.radius_attempts >= self.config.radius_attempt_limit)
             {
-                eprint!(
-                    "Success rate: {} ({} attempts, {} limit)",
-                    self.stats.radius_success_rate,
-                    self.stats.radius_attempts,
-                    self.config.radius_attempt_limit
-                );
-
                 // report stats
                 self.stats.radius_success_rate = radius_success_rate.rate().unwrap_or_default();
-                self.stats.delta = self.reference.delta(&self.current.img);
-                self.stats.elapsed = start_time.elapsed();
+                self.stats.elapsed = self.started.elapsed();
+
+                // adjust our radius
+                self.stats.radius -= self.radius_step_down();
+                self.observer
+                    .on_radius_changed(&self.stats, &self.current)?;
 
                 // reset our success rate calculator
                 radius_success_rate.reset();
@@ -140,43 +264,23 @@ impl Builder {
                 // reset successes and attempts
                 self.stats.radius_attempts = 0;
                 self.stats.radius_successes = 0;
-
-                // adjust our radius
-                self.stats.radius -= self.radius_step_down();
-                eprintln!(" ... new radius: {}", self.stats.radius);
             }
 
-            // if our radius hits the threshold we're done! Send the last update,
-            // write out the image, and return.
+            // if our radius hits the threshold we're done! Send the last update
+            // and return the shapes.
             if self.stats.radius < self.config.min_radius {
-                // send the last update to the GUI
-                self.send_updates();
-
-                // write out the image if specified
-                if let Some(img_path) = &self.config.output {
-                    self.current.save(img_path);
-                }
-
-                // write out the raw data if specified
-                if let Some(raw_path) = &self.config.raw {
-                    let mut writer = csv::Writer::from_path(raw_path).unwrap();
-                    for c in &self.circles {
-                        writer.serialize(c).unwrap();
-                    }
-                }
-
-                return;
+                return self.finish();
             }
 
             // ATTEMPT A NEW CIRCLE ------------------------------------------------------------
 
             // Picks the CENTER POINT of the region to be examined. This allows
-            // us to draw shapes that overlap the edges of the image. The random
-            // point selector always returns Some() so unwrap() is safe here
-            // ... unlike everywhere else, haha.
+            // us to draw shapes that overlap the edges of the image. Both point
+            // selectors always return Some() on a non-empty image, so unwrap()
+            // is safe here ... unlike everywhere else, haha.
             let (center_x, center_y) = point_selector.next().unwrap();
 
-            let reference_color = ColorPicker::sample(&self.reference, center_x, center_y);
+            let reference_color = self.color_picker.pick(&self.reference, center_x, center_y);
             let current_color = ColorPicker::sample(&self.current, center_x, center_y);
 
             // if reference pixel is the same as the current pixel, then skip
@@ -185,79 +289,529 @@ impl Builder {
                 continue;
             }
 
-            let region = Region::new(center_x, center_y, self.stats.radius);
+            let circle = Circle::new(center_x, center_y, self.stats.radius, reference_color);
+
+            // when packing, there's no room for a circle that would touch one
+            // already placed. Like a matching pixel, that says nothing about
+            // how well this radius fits, so it isn't counted as a miss; once
+            // space runs out the attempt limit moves the radius along.
+            if let Some(packing) = &self.packing {
+                let gap = self.config.min_gap;
+                if !packing
+                    .overlapping_circle(&self.circles, &circle, gap)
+                    .is_empty()
+                {
+                    continue;
+                }
+            }
+
+            let attempt = match (self.config.mode, self.screen) {
+                // stippling judges tone over a window around the dot, always at
+                // full resolution
+                (BuildMode::Stipple, None) => {
+                    Self::stipple_attempt(&self.reference, &self.current, &circle)
+                }
+                // as does a halftone plate, over the dot's screen cell
+                (BuildMode::Stipple, Some((pitch, _))) => {
+                    Self::screen_attempt(&self.reference, &self.current, &circle, pitch)
+                }
+                // large circles are evaluated on a downscaled level of the
+                // pyramid, if one is available; everything else runs at full
+                // resolution
+                (BuildMode::Standard | BuildMode::Pack, _) => {
+                    match self.pyramid.level_for(self.stats.radius) {
+                        Some(index) => {
+                            let level = self.pyramid.level(index);
+                            Self::attempt(
+                                &level.reference,
+                                &level.current,
+                                None,
+                                &level.scale_circle(&circle),
+                                self.config.similarity_threshold,
+                            )
+                        }
+                        None => Self::attempt(
+                            &self.reference,
+                            &self.current,
+                            Some(&self.errors),
+                            &circle,
+                            self.config.similarity_threshold,
+                        ),
+                    }
+                }
+                (BuildMode::Cmyk | BuildMode::Grid, _) => {
+                    unreachable!("laid out in one pass, before the search loop")
+                }
+            };
 
-            // get the delta between the reference and the current; if it's within
-            // a certain threshold, skip modifying it
-            let reference_crop = self.reference.section(&region);
-            let current_crop = self.current.section(&region);
+            match attempt {
+                Attempt::Skip => {
+                    // Skip ahead if this region is already looking really good.
+                    self.stats.total_skips += 1;
+                    radius_success_rate.sample(0);
+                }
+                Attempt::Reject => {
+                    radius_success_rate.sample(0);
+                }
+                Attempt::Accept => {
+                    self.current.draw_circle(&circle);
 
-            let reference_region_value = reference_crop.value();
-            let current_region_value = current_crop.value();
+                    // refresh the error of the pixels we just changed; a drawn circle
+                    // reaches one pixel past the edge of its region
+                    let dirty = Region::new(center_x, center_y, self.stats.radius + 1);
+                    self.errors.update(&self.reference, &self.current, &dirty);
+                    self.stats.delta = self.errors.total();
 
-            let region_similarity = (current_region_value as f32) / (reference_region_value as f32);
+                    // keep the coarse levels in step with the full resolution image
+                    self.pyramid.draw_circle(&circle);
 
-            // Skip ahead if this region is already looking really good.
-            if (region_similarity < 1.0) && (region_similarity > self.config.similarity_threshold) {
-                self.stats.total_skips += 1;
-                radius_success_rate.sample(0);
-                continue;
+                    // save the circle, always in full resolution coordinates
+                    if let Some(packing) = &mut self.packing {
+                        packing.insert(self.circles.len(), &circle);
+                    }
+                    self.circles.push(circle);
+
+                    radius_success_rate.sample(1);
+                    self.stats.radius_successes += 1;
+                    self.stats.total_successes += 1;
+
+                    // nice! tell whoever's watching
+                    self.observer.on_shape_committed(&circle, &self.stats)?;
+                }
             }
+        }
+    }
 
-            // work from a crop of the current best image
-            let mut candidate_crop = current_crop.clone();
-
-            // let's draw a circle!
-            let circle = Circle::new(
-                candidate_crop.center_x as u32,
-                candidate_crop.center_y as u32,
-                self.stats.radius,
-                reference_color,
-            );
-
-            // draw the circle on the candidate crop
-            candidate_crop.draw_circle(&circle);
-
-            // check the deltas from that region
-            let candidate_delta = reference_crop.delta(&candidate_crop.img);
-            let current_delta = reference_crop.delta(&current_crop.img);
-
-            // if candidate is closer to the reference than the current best,
-            // promote it to current!
-            if candidate_delta < current_delta {
-                // copy the candidate crop into the current image; marginally faster
-                // than just redrawing on the image
-                self.current
-                    .img
-                    .copy_from(
-                        &candidate_crop.img,
-                        region.real_origin_x(),
-                        region.real_origin_y(),
-                    )
-                    .unwrap();
-
-                // create and save the circle
-                let circle = Circle::new(center_x, center_y, self.stats.radius, reference_color);
-                self.circles.push(circle);
-
-                radius_success_rate.sample(1);
-                self.stats.radius_successes += 1;
-                self.stats.total_successes += 1;
-
-                // nice! update the UI
-                self.update_ui();
-            } else {
-                radius_success_rate.sample(0);
+    /// Reports the end of the build and hands back the shapes
+    fn finish(&mut self) -> Result<ShapeList> {
+        self.stats.elapsed = self.started.elapsed();
+        self.observer.on_finished(&self.stats, &self.current)?;
+
+        Ok(ShapeList {
+            circles: self.circles.clone(),
+            palette: self.color_picker.palette.clone(),
+            background: self.background,
+            plates: (self.config.mode == BuildMode::Cmyk).then(|| self.plates.clone()),
+            size: Some((self.reference.width(), self.reference.height())),
+        })
+    }
+
+    /// Builds each CMYK plate against its own separation of the reference:
+    /// black dots on white, centered on the cells of the plate's screen so each
+    /// ink keeps its angle, then colored with the plate's ink. Dot radii are at
+    /// most the screen pitch. The current image becomes the subtractive
+    /// preview of all four.
+    fn run_separations(&mut self) -> Result<()> {
+        let (width, height) = (self.reference.width(), self.reference.height());
+        let pitch = self.config.pitch;
+        let max_radius = self.config.max_radius.min(pitch);
+
+        let inks = halftone::PLATES.map(|p| p.ink);
+        let mut plates: [Vec<Circle>; 4] = Default::default();
+        for (index, plate) in halftone::PLATES.iter().enumerate() {
+            // every radius gets a full pass over the screen's cells, however few
+            // of them it fits: a light plate takes few dots at any radius
+            let cells = halftone::screen(width, height, pitch, plate.angle).len();
+            let options = BuildOptions {
+                mode: BuildMode::Stipple,
+                ink: "#000000".to_owned(),
+                max_radius,
+                min_radius: self.config.min_radius.min(max_radius),
+                radius_shrink_threshold: 0.0,
+                radius_attempt_limit: cells,
+                ..self.config.clone()
+            };
+
+            let separation = halftone::separation(&self.reference, index);
+            let builder = Builder::new(separation, options)?.with_screen(pitch, plate.angle);
+            let (shapes, stats) = self.run_plate(builder)?;
+
+            self.stats.total_attempts += stats.total_attempts;
+            self.stats.total_successes += stats.total_successes;
+            self.stats.total_skips += stats.total_skips;
+            self.stats.radius = stats.radius;
+
+            plates[index] = shapes
+                .circles
+                .iter()
+                .map(|c| Circle::new(c.x, c.y, c.radius, plate.ink))
+                .collect();
+            self.circles.extend(&plates[index]);
+            self.plates
+                .extend(std::iter::repeat_n(index, plates[index].len()));
+
+            // progress on later plates shows the ones already built
+            self.current = halftone::composite(&plates, &inks, width, height);
+            self.stats.delta = self.reference.delta(&self.current.img);
+        }
+
+        Ok(())
+    }
+
+    /// Builds one plate on its own thread, passing its progress on to the
+    /// observer as it goes, counted on top of the plates already built
+    fn run_plate(&mut self, builder: Builder) -> Result<(ShapeList, Stats)> {
+        let (tx, rx) = channel();
+        let mut builder = match self.progress_interval {
+            Some(interval) => builder
+                .with_observer(Channel::new(tx))
+                .with_progress(interval),
+            None => {
+                drop(tx);
+                builder
+            }
+        };
+
+        thread::scope(|scope| {
+            let plate = scope.spawn(move || {
+                let shapes = builder.run()?;
+                Ok((shapes, builder.stats()))
+            });
+
+            // ends when the plate's builder is done and drops its sender;
+            // returning early drops the receiver, which stops the plate too
+            for update in rx {
+                if let BuilderUpdate::Stats(plate) = update {
+                    let stats = Stats {
+                        total_attempts: self.stats.total_attempts + plate.total_attempts,
+                        total_successes: self.stats.total_successes + plate.total_successes,
+                        total_skips: self.stats.total_skips + plate.total_skips,
+                        delta: self.stats.delta,
+                        elapsed: self.started.elapsed(),
+                        ..plate
+                    };
+                    self.observer.on_progress(&stats, &self.current)?;
+                }
+            }
+
+            plate.join().unwrap()
+        })
+    }
+
+    /// Lays the reference out on a grid of equal circles, reporting progress
+    /// as they're drawn
+    fn run_mosaic(&mut self) -> Result<()> {
+        let palette = self.color_picker.palette.as_ref();
+        self.circles = mosaic::build(
+            &self.reference,
+            self.config.pitch,
+            self.config.lattice,
+            palette,
+            self.config.dither,
+        );
+
+        for i in 0..self.circles.len() {
+            self.current.draw_circle(&self.circles[i]);
+            self.stats.total_attempts += 1;
+            self.stats.total_successes += 1;
+            self.report_progress()?;
+        }
+
+        self.stats.delta = self.reference.delta(&self.current.img);
+        Ok(())
+    }
+
+    /// Stippling judges dots by tone rather than pixel by pixel: a dot is kept
+    /// if it brings the mean luminance of the window around it (a box blur)
+    /// closer to the reference's, so dot density ends up following darkness.
+    fn stipple_attempt(reference: &Canvas, current: &Canvas, circle: &Circle) -> Attempt {
+        let window = Region::new(circle.x, circle.y, circle.radius * STIPPLE_WINDOW);
+
+        let reference_tone = reference.section(&window).luminance();
+        let current_crop = current.section(&window);
+
+        let mut candidate_crop = current_crop.clone();
+        candidate_crop.draw_circle(&Circle {
+            x: candidate_crop.center_x as u32,
+            y: candidate_crop.center_y as u32,
+            ..*circle
+        });
+
+        let current_error = (current_crop.luminance() - reference_tone).abs();
+        let candidate_error = (candidate_crop.luminance() - reference_tone).abs();
+
+        if candidate_error < current_error {
+            Attempt::Accept
+        } else {
+            Attempt::Reject
+        }
+    }
+
+    /// Halftone dots are judged by the tone of their screen cell: a dot is kept
+    /// if it darkens the cell without taking it past the reference's tone, so
+    /// each cell ends up with about the largest dot its ink coverage allows.
+    /// Unlike stippling, a cell is never darkened past its tone to make up for
+    /// a lighter one next to it.
+    fn screen_attempt(
+        reference: &Canvas,
+        current: &Canvas,
+        circle: &Circle,
+        pitch: u32,
+    ) -> Attempt {
+        let cell = Region::new(circle.x, circle.y, pitch.div_ceil(2));
+
+        let reference_tone = reference.section(&cell).luminance();
+        let current_crop = current.section(&cell);
+
+        let mut candidate_crop = current_crop.clone();
+        candidate_crop.draw_circle(&Circle {
+            x: candidate_crop.center_x as u32,
+            y: candidate_crop.center_y as u32,
+            ..*circle
+        });
+
+        let candidate_tone = candidate_crop.luminance();
+        if candidate_tone < current_crop.luminance() && candidate_tone >= reference_tone {
+            Attempt::Accept
+        } else {
+            Attempt::Reject
+        }
+    }
+
+    /// Tests a candidate circle (in the coordinates of the given canvases)
+    /// against the region of the reference it covers. If an error map for the
+    /// canvases is given, both the current and the candidate's delta are read
+    /// from it, so nothing is copied or drawn to find out.
+    fn attempt(
+        reference: &Canvas,
+        current: &Canvas,
+        errors: Option<&ErrorMap>,
+        circle: &Circle,
+        similarity_threshold: f32,
+    ) -> Attempt {
+        let region = Region::new(circle.x, circle.y, circle.radius);
+
+        // get the delta between the reference and the current; if it's within
+        // a certain threshold, skip modifying it
+        let reference_region_value = reference.region_value(&region);
+        let current_region_value = current.region_value(&region);
+
+        let region_similarity = (current_region_value as f32) / (reference_region_value as f32);
+
+        if (region_similarity < 1.0) && (region_similarity > similarity_threshold) {
+            return Attempt::Skip;
+        }
+
+        // check the deltas from that region, with and without the circle
+        let (candidate_delta, current_delta) = match errors {
+            Some(errors) => {
+                // a drawn circle reaches one pixel past the edge of its region
+                let reach = Region::new(circle.x, circle.y, circle.radius + 1);
+                (
+                    errors.region_sum_with_circle(reference, &reach, circle),
+                    errors.region_sum(&reach),
+                )
+            }
+            None => {
+                let reference_crop = reference.section(&region);
+                let current_crop = current.section(&region);
+
+                // work from a crop of the current best image
+                let mut candidate_crop = current_crop.clone();
+
+                // let's draw a circle!
+                candidate_crop.draw_circle(&Circle {
+                    x: candidate_crop.center_x as u32,
+                    y: candidate_crop.center_y as u32,
+                    ..*circle
+                });
+
+                (
+                    reference_crop.delta(&candidate_crop.img),
+                    reference_crop.delta(&current_crop.img),
+                )
             }
+        };
+
+        // if candidate is closer to the reference than the current best,
+        // promote it to current!
+        if candidate_delta < current_delta {
+            Attempt::Accept
+        } else {
+            Attempt::Reject
         }
     }
 }
 
-pub struct ColorPicker {}
+pub struct ColorPicker {
+    // when set, every picked color is snapped to its nearest palette entry
+    palette: Option<Palette>,
+}
 
 impl ColorPicker {
+    /// The color a new shape centered on the given pixel should be
+    pub fn pick(&self, image_set: &Canvas, x: u32, y: u32) -> Rgba<u8> {
+        let color = Self::sample(image_set, x, y);
+
+        match &self.palette {
+            Some(palette) => palette.nearest(color),
+            None => color,
+        }
+    }
+
     pub fn sample(image_set: &Canvas, x: u32, y: u32) -> Rgba<u8> {
         use imageproc::drawing::Canvas; // namespace collision for get_pixel
         image_set.img.get_pixel(x, y)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    /// Sends the shape count at each progress report
+    struct Progress(Sender<usize>);
+
+    impl Observer for Progress {
+        fn on_progress(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+            self.0
+                .send(stats.total_successes)
+                .map_err(|_| Error::ChannelClosed)
+        }
+    }
+
+    #[test]
+    fn reports_progress_without_placing_shapes() {
+        // the reference is the blank canvas the build starts from, so every
+        // radius goes by without a shape
+        let options = BuildOptions {
+            max_radius: 4,
+            min_radius: 1,
+            radius_attempt_limit: 500,
+            ..Default::default()
+        };
+        let (tx, rx) = channel();
+        let mut builder = Builder::new(Canvas::new(32, 32), options)
+            .unwrap()
+            .with_observer(Progress(tx))
+            .with_progress(Duration::ZERO);
+        let shapes = builder.run().unwrap();
+        drop(builder);
+
+        assert!(shapes.circles.is_empty());
+        let reports: Vec<usize> = rx.iter().collect();
+        assert!(!reports.is_empty());
+        assert!(reports.iter().all(|shapes| *shapes == 0));
+    }
+
+    #[test]
+    fn reports_progress_while_building_plates() {
+        let options = BuildOptions {
+            mode: BuildMode::Cmyk,
+            radius_attempt_limit: 200,
+            ..Default::default()
+        };
+        let (tx, rx) = channel();
+        let mut builder = Builder::new(Canvas::filled(32, 32, Rgba([0, 255, 255, 255])), options)
+            .unwrap()
+            .with_observer(Progress(tx))
+            .with_progress(Duration::ZERO);
+        let shapes = builder.run().unwrap();
+        drop(builder);
+
+        // counted across the plates, so never going back
+        let reports: Vec<usize> = rx.iter().collect();
+        assert!(!reports.is_empty());
+        assert!(reports.windows(2).all(|w| w[0] <= w[1]));
+        assert!(reports.iter().all(|count| *count <= shapes.circles.len()));
+    }
+
+    #[test]
+    fn builds_transparent_pixels_by_their_color() {
+        // a dim red disc, fully transparent; drawing red over it should count
+        // as progress even though every drawn pixel is opaque
+        let mut reference = Canvas::new(48, 48);
+        reference.draw_circle(&Circle::new(24, 24, 14, Rgba([200, 0, 0, 255])));
+        for pixel in reference.img.as_mut_rgba8().unwrap().pixels_mut() {
+            pixel[3] = 0;
+        }
+        let start = reference.delta(&Canvas::new(48, 48).img);
+
+        let options = BuildOptions {
+            max_radius: 16,
+            min_radius: 2,
+            ..Default::default()
+        };
+        let mut builder = Builder::new(reference, options).unwrap();
+        let shapes = builder.run().unwrap();
+
+        assert!(!shapes.circles.is_empty());
+        assert_eq!(
+            builder.stats().delta,
+            builder.reference().delta(&builder.image().img)
+        );
+        assert!(builder.stats().delta < start / 4);
+    }
+
+    #[test]
+    fn stipples_dark_dots_that_follow_tone() {
+        // dark on the left, light on the right
+        let mut reference = Canvas::filled(64, 32, Rgba([200, 200, 200, 255]));
+        for (x, _, pixel) in reference.img.as_mut_rgba8().unwrap().enumerate_pixels_mut() {
+            if x < 32 {
+                *pixel = Rgba([60, 60, 60, 255]);
+            }
+        }
+
+        let options = BuildOptions {
+            mode: BuildMode::Stipple,
+            max_radius: 3,
+            min_radius: 1,
+            radius_attempt_limit: 3000,
+            ..Default::default()
+        };
+        let mut builder = Builder::new(reference, options).unwrap();
+        let shapes = builder.run().unwrap();
+
+        assert_eq!(shapes.background, Some(Rgba([255, 255, 255, 255])));
+        assert!(shapes.circles.iter().all(|c| (c.r, c.g, c.b) == (0, 0, 0)));
+
+        // more dots where it's darker
+        let dark_dots = shapes.circles.iter().filter(|c| c.x < 32).count();
+        assert!(dark_dots > shapes.circles.len() - dark_dots);
+
+        // and the halves keep their tones apart, measured away from the edge
+        // between them, where windows see both
+        let image = builder.image();
+        let tone = |x: u32| image.section(&Region::new(x, 16, 10)).luminance();
+        let (dark, light) = (tone(12), tone(52));
+        assert!(
+            light - dark > 80.0,
+            "dark half {} vs light half {}",
+            dark,
+            light
+        );
+    }
+
+    #[test]
+    fn builds_each_plate_from_its_separation() {
+        // cyan on the left, paper white on the right
+        let mut reference = Canvas::filled(64, 32, Rgba([255, 255, 255, 255]));
+        for (x, _, pixel) in reference.img.as_mut_rgba8().unwrap().enumerate_pixels_mut() {
+            if x < 32 {
+                *pixel = Rgba([0, 255, 255, 255]);
+            }
+        }
+
+        let options = BuildOptions {
+            mode: BuildMode::Cmyk,
+            radius_attempt_limit: 1000,
+            ..Default::default()
+        };
+        let mut builder = Builder::new(reference, options).unwrap();
+        let shapes = builder.run().unwrap();
+
+        let plates = shapes.plates.as_ref().unwrap();
+        assert_eq!(plates.len(), shapes.circles.len());
+        assert!(!plates.is_empty());
+        assert!(plates.iter().all(|p| *p == 0));
+
+        // dots are no larger than the screen, on the side with ink
+        let cyan = halftone::PLATES[0].ink;
+        for c in &shapes.circles {
+            assert_eq!(Rgba([c.r, c.g, c.b, 255]), cyan);
+            assert!(c.radius <= 8 && c.x < 32 + 8, "{:?}", c);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use image::{DynamicImage, GenericImageView, Rgba};
 
-use crate::{Circle, Region};
+use crate::{Circle, Error, Region, Result};
 
 #[derive(Clone)]
 pub struct Canvas {
@@ -11,11 +11,15 @@ pub struct Canvas {
 
 impl Canvas {
     pub fn new(width: u32, height: u32) -> Self {
+        // start with a black image
+        Self::filled(width, height, Rgba([0, 0, 0, 255]))
+    }
+
+    pub fn filled(width: u32, height: u32, color: Rgba<u8>) -> Self {
         let mut img = DynamicImage::new_rgba8(width, height);
 
-        // make our new image black
         for pixel in img.as_mut_rgba8().unwrap().pixels_mut() {
-            *pixel = Rgba([0, 0, 0, 255]);
+            *pixel = color;
         }
 
         Self {
@@ -25,14 +29,20 @@ impl Canvas {
         }
     }
 
-    pub fn open(path: &str) -> Result<Self, String> {
-        let img = image::open(path).map_err(|e| format!("{}", e))?;
+    pub fn open(path: &str) -> Result<Self> {
+        let img = image::open(path).map_err(|e| Error::image(path, e))?;
+        Ok(Self::from_image(img))
+    }
+
+    pub fn from_image(img: DynamicImage) -> Self {
+        // normalize to RGBA so byte-wise deltas line up with our working canvases
+        let img = DynamicImage::ImageRgba8(img.to_rgba8());
 
-        Ok(Self {
+        Self {
             center_x: (img.width() as i32) / 2,
             center_y: (img.height() as i32) / 2,
             img,
-        })
+        }
     }
 
     pub fn width(&self) -> u32 {
@@ -67,6 +77,49 @@ impl Canvas {
         value
     }
 
+    /// Sum of the color values within the region, clipped to the canvas; the
+    /// same as `section(region).value()`, without copying the pixels out
+    pub fn region_value(&self, region: &Region) -> usize {
+        let img = self.img.as_rgba8().unwrap();
+        let min_x = region.real_origin_x().min(self.width());
+        let min_y = region.real_origin_y().min(self.height());
+        let max_x = (min_x + region.real_width()).min(self.width());
+        let max_y = (min_y + region.real_height()).min(self.height());
+
+        let mut value = 0;
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                value += Self::pixel_value(*img.get_pixel(x, y));
+            }
+        }
+        value
+    }
+
+    /// Mean color of the whole canvas
+    pub fn mean_color(&self) -> Rgba<u8> {
+        let pixels = self.img.as_rgba8().unwrap().pixels();
+        let count = pixels.len().max(1);
+        let mut sums = [0usize; 3];
+        for p in pixels {
+            for c in 0..3 {
+                sums[c] += p[c] as usize;
+            }
+        }
+        Rgba([
+            (sums[0] / count) as u8,
+            (sums[1] / count) as u8,
+            (sums[2] / count) as u8,
+            255,
+        ])
+    }
+
+    /// Mean luminance of the whole canvas, 0.0 to 255.0
+    pub fn luminance(&self) -> f32 {
+        let pixels = self.img.as_rgba8().unwrap().pixels();
+        let count = pixels.len().max(1);
+        pixels.map(|p| Self::pixel_luminance(*p)).sum::<f32>() / count as f32
+    }
+
     fn pixel_value(a: Rgba<u8>) -> usize {
         let mut value: usize = 0;
 
@@ -77,12 +130,13 @@ impl Canvas {
         value
     }
 
+    /// Perceived brightness (Rec. 601 weights), 0.0 to 255.0
+    pub fn pixel_luminance(a: Rgba<u8>) -> f32 {
+        0.299 * a[0] as f32 + 0.587 * a[1] as f32 + 0.114 * a[2] as f32
+    }
+
     fn channel_delta(a: u8, b: u8) -> u8 {
-        if a >= b {
-            a - b
-        } else {
-            b - a
-        }
+        a.abs_diff(b)
     }
 
     pub fn pixel_delta(a: Rgba<u8>, b: Rgba<u8>) -> usize {
@@ -95,16 +149,16 @@ impl Canvas {
     }
 
     pub fn byte_delta(a: u8, b: u8) -> usize {
-        if a >= b {
-            (a - b) as usize
-        } else {
-            (b - a) as usize
-        }
+        a.abs_diff(b) as usize
     }
 
+    /// Sum of the differences in color between the two images, pixel by
+    /// pixel. Like `pixel_delta`, this leaves alpha out: shapes are always
+    /// drawn opaque, so a transparent reference would otherwise count against
+    /// every shape drawn over it.
     pub fn delta(&self, img: &DynamicImage) -> usize {
-        std::iter::zip(self.img.as_bytes(), img.as_bytes())
-            .map(|(a, b)| Self::byte_delta(*a, *b)) // 0 and 1 are coordinates; 2 is the pixel
+        std::iter::zip(self.img.as_bytes().chunks(4), img.as_bytes().chunks(4))
+            .map(|(a, b)| (0..3).map(|c| Self::byte_delta(a[c], b[c])).sum::<usize>())
             .sum()
     }
 
@@ -128,7 +182,7 @@ impl Canvas {
         );
     }
 
-    pub fn save(&self, path: &str) {
-        self.img.save(path).unwrap();
+    pub fn save(&self, path: &str) -> Result<()> {
+        self.img.save(path).map_err(|e| Error::image(path, e))
     }
 }
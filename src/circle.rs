@@ -29,6 +29,35 @@ impl Circle {
         }
     }
 
+    /// The pixels this circle covers when drawn, row by row, as (y, first x,
+    /// last x) and not clipped to any image. These trace the same midpoint
+    /// circle `Canvas::draw_circle` fills.
+    pub fn rows(&self) -> impl Iterator<Item = (i64, i64, i64)> {
+        let radius = self.radius as i64;
+
+        // half the width of each row, by distance from the center row
+        let mut half_widths = vec![0; radius as usize + 1];
+        let (mut x, mut y, mut p) = (0, radius, 1 - radius);
+        while x <= y {
+            half_widths[y as usize] = half_widths[y as usize].max(x);
+            half_widths[x as usize] = half_widths[x as usize].max(y);
+
+            x += 1;
+            if p < 0 {
+                p += 2 * x + 1;
+            } else {
+                y -= 1;
+                p += 2 * (x - y) + 1;
+            }
+        }
+
+        let (cx, cy) = (self.x as i64, self.y as i64);
+        (-radius..=radius).map(move |dy| {
+            let half_width = half_widths[dy.unsigned_abs() as usize];
+            (cy + dy, cx - half_width, cx + half_width)
+        })
+    }
+
     fn center_to_center_distance(&self, other: &Circle) -> f32 {
         let x_diff = self.x as f32 - other.x as f32;
         let y_diff = self.y as f32 - other.y as f32;
@@ -40,6 +69,13 @@ impl Circle {
         self.center_to_center_distance(other) < (self.radius + other.radius) as f32
     }
 
+    /// True if every pixel of the other circle is also painted by this one.
+    /// Rasterized edges can stray up to half a pixel from the true radius, so
+    /// this leaves a couple of pixels of slack rather than testing exactly.
+    pub fn contains_circle(&self, other: &Circle) -> bool {
+        self.center_to_center_distance(other) + (other.radius + 2) as f32 <= self.radius as f32
+    }
+
     pub fn overlaps_region(&self, region: &Region) -> bool {
         let cx = self.x as f32;
         let cy = self.y as f32;
@@ -0,0 +1,187 @@
+use crate::{palette::Palette, Canvas, Circle};
+use image::Rgba;
+
+/// A recoloring applied to finished shapes at render time
+#[derive(Debug, Clone)]
+pub enum ColorTransform {
+    /// Snap every color to the nearest palette entry. If the shapes were built
+    /// against a recorded palette of the same size, entries are swapped by
+    /// position instead, so the new palette maps one-to-one onto the old.
+    Remap {
+        palette: Palette,
+        source: Option<Palette>,
+    },
+    /// Replace each color with a point on a gradient, chosen by its luminance
+    GradientMap(Vec<Rgba<u8>>),
+    /// Rotate hue by the given number of degrees
+    HueShift(f32),
+    Grayscale,
+    Invert,
+}
+
+impl ColorTransform {
+    pub fn apply(&self, color: Rgba<u8>) -> Rgba<u8> {
+        match self {
+            Self::Remap { palette, source } => Self::remap(palette, source.as_ref(), color),
+            Self::GradientMap(stops) => Self::gradient_map(stops, color),
+            Self::HueShift(degrees) => Self::hue_shift(*degrees, color),
+            Self::Grayscale => {
+                let l = Canvas::pixel_luminance(color).round() as u8;
+                Rgba([l, l, l, 255])
+            }
+            Self::Invert => Rgba([255 - color[0], 255 - color[1], 255 - color[2], 255]),
+        }
+    }
+
+    /// Runs every transform, in order, over each circle's color
+    pub fn apply_all(transforms: &[ColorTransform], circles: &[Circle]) -> Vec<Circle> {
+        circles
+            .iter()
+            .map(|c| {
+                let color = Self::apply_color(transforms, Rgba([c.r, c.g, c.b, 255]));
+                Circle::new(c.x, c.y, c.radius, color)
+            })
+            .collect()
+    }
+
+    pub fn apply_color(transforms: &[ColorTransform], color: Rgba<u8>) -> Rgba<u8> {
+        transforms.iter().fold(color, |color, t| t.apply(color))
+    }
+
+    fn remap(palette: &Palette, source: Option<&Palette>, color: Rgba<u8>) -> Rgba<u8> {
+        if let Some(source) = source.filter(|s| s.entries.len() == palette.entries.len()) {
+            if let Some(i) = source.entries.iter().position(|e| e.color == color) {
+                return palette.entries[i].color;
+            }
+        }
+
+        palette.nearest(color)
+    }
+
+    fn gradient_map(stops: &[Rgba<u8>], color: Rgba<u8>) -> Rgba<u8> {
+        match stops.len() {
+            0 => color,
+            1 => stops[0],
+            _ => {
+                // position along the gradient, split into a segment and the
+                // fraction of the way through it
+                let position = Canvas::pixel_luminance(color) / 255.0 * (stops.len() - 1) as f32;
+                let segment = (position.floor() as usize).min(stops.len() - 2);
+                let t = position - segment as f32;
+
+                let (a, b) = (stops[segment], stops[segment + 1]);
+                let mix = |c: usize| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * t).round() as u8;
+                Rgba([mix(0), mix(1), mix(2), 255])
+            }
+        }
+    }
+
+    fn hue_shift(degrees: f32, color: Rgba<u8>) -> Rgba<u8> {
+        let (h, s, l) = Self::to_hsl(color);
+        Self::from_hsl((h + degrees).rem_euclid(360.0), s, l)
+    }
+
+    // hue in degrees, saturation and lightness in 0..1
+    fn to_hsl(color: Rgba<u8>) -> (f32, f32, f32) {
+        let r = color[0] as f32 / 255.0;
+        let g = color[1] as f32 / 255.0;
+        let b = color[2] as f32 / 255.0;
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let d = max - min;
+
+        if d == 0.0 {
+            return (0.0, 0.0, l);
+        }
+
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * ((g - b) / d).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / d + 2.0)
+        } else {
+            60.0 * ((r - g) / d + 4.0)
+        };
+
+        (h, s, l)
+    }
+
+    fn from_hsl(h: f32, s: f32, l: f32) -> Rgba<u8> {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+        let m = l - c / 2.0;
+
+        let (r, g, b) = match (h / 60.0) as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Rgba([channel(r), channel(g), channel(b), 255])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+
+    #[test]
+    fn shifts_hue() {
+        assert_eq!(ColorTransform::HueShift(120.0).apply(RED), GREEN);
+        assert_eq!(ColorTransform::HueShift(-240.0).apply(RED), GREEN);
+
+        let muted = Rgba([120, 80, 60, 255]);
+        assert_eq!(ColorTransform::HueShift(360.0).apply(muted), muted);
+    }
+
+    #[test]
+    fn maps_gradients_by_luminance() {
+        let duotone =
+            ColorTransform::GradientMap(vec![Rgba([0, 0, 64, 255]), Rgba([255, 200, 0, 255])]);
+
+        assert_eq!(duotone.apply(Rgba([0, 0, 0, 255])), Rgba([0, 0, 64, 255]));
+        assert_eq!(
+            duotone.apply(Rgba([255, 255, 255, 255])),
+            Rgba([255, 200, 0, 255])
+        );
+    }
+
+    #[test]
+    fn swaps_recorded_palettes_by_position() {
+        let source = Palette::parse_hex_list("#ff0000 #00ff00").unwrap();
+        let palette = Palette::parse_hex_list("#000000 #ffffff").unwrap();
+        let remap = ColorTransform::Remap {
+            palette: palette.clone(),
+            source: Some(source),
+        };
+
+        // green was entry 1, so it becomes white even though black is nearer
+        assert_eq!(remap.apply(RED), Rgba([0, 0, 0, 255]));
+        assert_eq!(remap.apply(GREEN), Rgba([255, 255, 255, 255]));
+
+        let nearest = ColorTransform::Remap {
+            palette,
+            source: None,
+        };
+        assert_eq!(
+            nearest.apply(Rgba([200, 200, 200, 255])),
+            Rgba([255, 255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn chains_in_order() {
+        let transforms = [ColorTransform::Grayscale, ColorTransform::Invert];
+        let color = ColorTransform::apply_color(&transforms, Rgba([255, 255, 255, 255]));
+        assert_eq!(color, Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{metrics, output::Output, Canvas, CompareConfig, Error, Render, Result, ShapeList};
+use image::{GenericImageView, Rgba};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// One of the two files being compared
+#[derive(Serialize, Debug)]
+pub struct Side {
+    pub path: String,
+    pub shapes: usize,
+    pub bytes: u64,
+    // the rest are measured against the reference, when there is one
+    pub delta: Option<usize>,
+    pub psnr: Option<f64>,
+    pub ssim: Option<f64>,
+    // fraction of pixels where this side is strictly closer to the reference
+    pub closer: Option<f64>,
+}
+
+/// Two raw files rendered at the same size and measured against each other,
+/// and against a reference image if there is one
+#[derive(Serialize, Debug)]
+pub struct Comparison {
+    pub width: u32,
+    pub height: u32,
+    pub first: Side,
+    pub second: Side,
+    // between the two renders
+    pub delta: usize,
+    pub psnr: f64,
+    pub ssim: f64,
+}
+
+/// The `compare` subcommand
+pub struct Comparer {
+    config: CompareConfig,
+    output: Output,
+}
+
+impl Comparer {
+    pub fn new(config: CompareConfig) -> Self {
+        Self {
+            config,
+            output: Output::default(),
+        }
+    }
+
+    /// Where progress and the result are reported
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let first = ShapeList::read(&self.config.first)?;
+        let second = ShapeList::read(&self.config.second)?;
+        let reference = match &self.config.reference {
+            Some(path) => Some(Canvas::open(path)?),
+            None => None,
+        };
+
+        let (width, height) = Self::size(&first, &second, reference.as_ref());
+        let a = Render::render_shapes_on(&first, width, height);
+        let b = Render::render_shapes_on(&second, width, height);
+
+        let comparison = Comparison {
+            width,
+            height,
+            first: Self::side(&self.config.first, &first, &a, &b, reference.as_ref())?,
+            second: Self::side(&self.config.second, &second, &b, &a, reference.as_ref())?,
+            delta: a.delta(&b.img),
+            psnr: metrics::psnr(&a, &b),
+            ssim: metrics::ssim(&a, &b),
+        };
+
+        if let Some(path) = &self.config.diff {
+            Self::diff(&a, &b, reference.as_ref()).save(path)?;
+        }
+
+        let outputs: Vec<_> = self.config.diff.iter().collect();
+        self.output
+            .report(&comparison, Comparison::to_text, &outputs);
+
+        Ok(())
+    }
+
+    /// Both are rendered at the reference's size, failing that the size the
+    /// first file was built at, and failing that whatever holds both
+    fn size(first: &ShapeList, second: &ShapeList, reference: Option<&Canvas>) -> (u32, u32) {
+        if let Some(reference) = reference {
+            return (reference.width(), reference.height());
+        }
+
+        first.size.or(second.size).unwrap_or_else(|| {
+            let circles = || first.circles.iter().chain(second.circles.iter());
+            (
+                circles().map(|c| c.x + c.radius).max().unwrap_or(0),
+                circles().map(|c| c.y + c.radius).max().unwrap_or(0),
+            )
+        })
+    }
+
+    fn side(
+        path: &str,
+        shapes: &ShapeList,
+        render: &Canvas,
+        other: &Canvas,
+        reference: Option<&Canvas>,
+    ) -> Result<Side> {
+        let bytes = std::fs::metadata(path)
+            .map_err(|e| Error::io(path, e))?
+            .len();
+
+        let closer = reference.map(|reference| {
+            let closer = reference
+                .img
+                .pixels()
+                .filter(|(x, y, p)| {
+                    let mine = Canvas::pixel_delta(render.img.get_pixel(*x, *y), *p);
+                    let theirs = Canvas::pixel_delta(other.img.get_pixel(*x, *y), *p);
+                    mine < theirs
+                })
+                .count();
+            closer as f64 / (render.width() as f64 * render.height() as f64).max(1.0)
+        });
+
+        Ok(Side {
+            path: path.to_owned(),
+            shapes: shapes.circles.len(),
+            bytes,
+            delta: reference.map(|r| r.delta(&render.img)),
+            psnr: reference.map(|r| metrics::psnr(r, render)),
+            ssim: reference.map(|r| metrics::ssim(r, render)),
+            closer,
+        })
+    }
+
+    /// Where the renders differ. Against a reference, each pixel is tinted
+    /// green where the first is closer, magenta where the second is, over a
+    /// dimmed copy of the reference; the stronger the tint, the bigger the
+    /// gap. Without one, brighter pixels simply differ more.
+    fn diff(a: &Canvas, b: &Canvas, reference: Option<&Canvas>) -> Canvas {
+        let mut output = Canvas::new(a.width(), a.height());
+        let out = output.img.as_mut_rgba8().unwrap();
+
+        for (x, y, pixel) in out.enumerate_pixels_mut() {
+            let pa = a.img.get_pixel(x, y);
+            let pb = b.img.get_pixel(x, y);
+
+            *pixel = match reference {
+                Some(reference) => {
+                    let p = reference.img.get_pixel(x, y);
+                    let base = (Canvas::pixel_luminance(p) / 4.0) as u8;
+                    let ea = Canvas::pixel_delta(pa, p);
+                    let eb = Canvas::pixel_delta(pb, p);
+                    let tint = |gap: usize| base.saturating_add((gap / 3) as u8);
+
+                    if ea < eb {
+                        Rgba([base, tint(eb - ea), base, 255])
+                    } else if eb < ea {
+                        Rgba([tint(ea - eb), base, tint(ea - eb), 255])
+                    } else {
+                        Rgba([base, base, base, 255])
+                    }
+                }
+                None => {
+                    let v = (Canvas::pixel_delta(pa, pb) / 3) as u8;
+                    Rgba([v, v, v, 255])
+                }
+            };
+        }
+
+        output
+    }
+}
+
+impl Comparison {
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        let row = |output: &mut String, label: &str, a: String, b: String| {
+            writeln!(output, "{:<8}  {:>14}  {:>14}", label, a, b).unwrap();
+        };
+        let optional = |v: Option<String>| v.unwrap_or_else(|| "-".to_owned());
+
+        row(&mut output, "", "first".to_owned(), "second".to_owned());
+        row(
+            &mut output,
+            "shapes",
+            self.first.shapes.to_string(),
+            self.second.shapes.to_string(),
+        );
+        row(
+            &mut output,
+            "bytes",
+            self.first.bytes.to_string(),
+            self.second.bytes.to_string(),
+        );
+
+        let sides = [&self.first, &self.second];
+        let [a, b] = sides.map(|s| optional(s.delta.map(|d| d.to_string())));
+        row(&mut output, "delta", a, b);
+        let [a, b] = sides.map(|s| optional(s.psnr.map(metrics::psnr_text)));
+        row(&mut output, "psnr", a, b);
+        let [a, b] = sides.map(|s| optional(s.ssim.map(|p| format!("{:.4}", p))));
+        row(&mut output, "ssim", a, b);
+        let [a, b] = sides.map(|s| optional(s.closer.map(|c| format!("{:.1}%", c * 100.0))));
+        row(&mut output, "closer", a, b);
+
+        writeln!(
+            output,
+            "\nrendered at {}x{}; between them: delta {}, psnr {}, ssim {:.4}",
+            self.width,
+            self.height,
+            self.delta,
+            metrics::psnr_text(self.psnr),
+            self.ssim
+        )
+        .unwrap();
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Circle;
+
+    #[test]
+    fn tints_whichever_side_is_closer() {
+        let white = Rgba([255, 255, 255, 255]);
+        let mut reference = Canvas::new(20, 10);
+        reference.draw_circle(&Circle::new(5, 5, 3, white));
+        reference.draw_circle(&Circle::new(15, 5, 3, white));
+
+        // each gets one of the two dots right
+        let mut a = Canvas::new(20, 10);
+        a.draw_circle(&Circle::new(5, 5, 3, white));
+        let mut b = Canvas::new(20, 10);
+        b.draw_circle(&Circle::new(15, 5, 3, white));
+
+        let diff = Comparer::diff(&a, &b, Some(&reference));
+        let left = diff.img.get_pixel(5, 5);
+        let right = diff.img.get_pixel(15, 5);
+        assert!(left[1] > left[0]);
+        assert!(right[0] > right[1]);
+        assert_eq!(diff.img.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+    }
+}
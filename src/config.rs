@@ -0,0 +1,719 @@
+use crate::{output, Error, Result};
+#[cfg(feature = "cli")]
+use clap::{ArgGroup, Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+// most pyramid levels a build can use
+const MAX_PYRAMID_LEVELS: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildMode {
+    /// Overlapping circles in any color, on black
+    #[default]
+    Standard,
+    /// Dots of a single ink color on white, with density following tone
+    Stipple,
+    /// Four halftone plates (cyan, magenta, yellow, black) at screen angles
+    Cmyk,
+    /// Circles that never overlap, packed onto a background color
+    Pack,
+    /// Equal circles on a fixed grid, like beads or mosaic tiles
+    Grid,
+}
+
+/// How plotter output fills in circles
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Hatch {
+    /// Rings a pen width apart, each a separate stroke
+    #[default]
+    Concentric,
+    /// A single stroke spiraling in to the center
+    Spiral,
+}
+
+/// Arrangement of grid mode's cells
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Lattice {
+    /// Rows and columns
+    #[default]
+    Square,
+    /// Every other row shifted by half a cell, packed closer together
+    Hex,
+}
+
+/// How a command reports: to people, or to other programs
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Aligned, human-readable text, with progress on stderr
+    #[default]
+    Text,
+    /// JSON lines on stdout, one per event, ending with the result
+    Json,
+}
+
+/// How `sediment tune` picks the settings it tries
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Search {
+    /// Every combination of a few values for each setting
+    #[default]
+    Grid,
+    /// Settings drawn at random from the same ranges
+    Random,
+}
+
+/// Starting points for a build, tuned for common jobs
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Large shapes judged on a downscaled image; seconds rather than minutes
+    FastPreview,
+    /// Small radius steps down to single pixels, with many attempts at each
+    PrintQuality,
+    /// Fine black dots on white, with tone carried by dot density
+    Stipple,
+}
+
+impl Preset {
+    pub fn options(self) -> BuildOptions {
+        let defaults = BuildOptions::default();
+        match self {
+            Self::FastPreview => BuildOptions {
+                max_radius: 200,
+                min_radius: 4,
+                radius_step: 0.3,
+                radius_attempt_limit: 1000,
+                pyramid_levels: 3,
+                ..defaults
+            },
+            Self::PrintQuality => BuildOptions {
+                min_radius: 1,
+                radius_shrink_threshold: 0.1,
+                radius_step: 0.05,
+                radius_attempt_limit: 20000,
+                similarity_threshold: 0.95,
+                ..defaults
+            },
+            Self::Stipple => BuildOptions {
+                mode: BuildMode::Stipple,
+                max_radius: 4,
+                min_radius: 1,
+                radius_shrink_threshold: 0.05,
+                radius_step: 0.25,
+                radius_attempt_limit: 20000,
+                ..defaults
+            },
+        }
+    }
+}
+
+/// Settings for a build. These are also the `sediment build` flags; outside
+/// the command line, start from `BuildOptions::default()` or a `Preset`.
+/// Config files use the same names as the long flags (`max-radius = 200`).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuildOptions {
+    /// Maximum radius of the shapes to be placed
+    #[cfg_attr(feature = "cli", arg(short = 'r', long, default_value_t = 500))]
+    pub max_radius: u32,
+
+    /// Minimum radius of the shapes to be placed
+    #[cfg_attr(feature = "cli", arg(short = 'm', long, default_value_t = 1))]
+    pub min_radius: u32,
+
+    /// Shrink the radius size when successes are lower than this rate
+    #[cfg_attr(feature = "cli", arg(short = 't', long, default_value_t = 0.2))]
+    pub radius_shrink_threshold: f32,
+
+    /// Amount to shrink the radius at each step
+    #[cfg_attr(feature = "cli", arg(short = 'p', long, default_value_t = 0.1))]
+    pub radius_step: f32,
+
+    /// Reduce the radius size after this many attempts are made
+    #[cfg_attr(feature = "cli", arg(short = 'a', long, default_value_t = 5000))]
+    pub radius_attempt_limit: usize,
+
+    /// Threshold for skipping shape placement
+    #[cfg_attr(feature = "cli", arg(short = 's', long, short, default_value_t = 0.9))]
+    pub similarity_threshold: f32,
+
+    /// Number of image pyramid levels; large radii are evaluated on
+    /// downscaled copies of the image (1 disables the pyramid)
+    #[cfg_attr(feature = "cli", arg(short = 'l', long, default_value_t = 1))]
+    pub pyramid_levels: usize,
+
+    /// Kind of image to build
+    #[cfg_attr(feature = "cli", arg(short = 'd', long, value_enum, default_value_t = BuildMode::Standard))]
+    pub mode: BuildMode,
+
+    /// Ink color for stipple mode
+    #[cfg_attr(feature = "cli", arg(short = 'k', long, default_value = "#000000"))]
+    pub ink: String,
+
+    /// Distance between dot centers in cmyk and grid modes, in pixels
+    #[cfg_attr(
+        feature = "cli",
+        arg(short = 'w', long, visible_alias = "screen-pitch", default_value_t = 8)
+    )]
+    pub pitch: u32,
+
+    /// Arrangement of cells in grid mode
+    #[cfg_attr(feature = "cli", arg(short = 'e', long, value_enum, default_value_t = Lattice::Square))]
+    pub lattice: Lattice,
+
+    /// In grid mode, carry each cell's color error on to its neighbours
+    /// (needs a palette)
+    #[cfg_attr(feature = "cli", arg(short = 'f', long))]
+    pub dither: bool,
+
+    /// Background color the circles are placed on in pack and grid modes
+    #[cfg_attr(feature = "cli", arg(short = 'b', long, default_value = "#000000"))]
+    pub background: String,
+
+    /// Minimum space between packed circles, in pixels
+    #[cfg_attr(feature = "cli", arg(short = 'n', long, default_value_t = 0))]
+    pub min_gap: u32,
+
+    /// Restrict shape colors to a palette: a list of hex colors
+    /// ("#ff0000,#000000"), a GIMP .gpl file, or "auto N" to quantize the
+    /// input down to N colors (ignored when stippling)
+    #[cfg_attr(feature = "cli", arg(short = 'c', long))]
+    pub palette: Option<String>,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            max_radius: 500,
+            min_radius: 1,
+            radius_shrink_threshold: 0.2,
+            radius_step: 0.1,
+            radius_attempt_limit: 5000,
+            similarity_threshold: 0.9,
+            pyramid_levels: 1,
+            mode: BuildMode::Standard,
+            ink: "#000000".to_owned(),
+            pitch: 8,
+            lattice: Lattice::Square,
+            dither: false,
+            background: "#000000".to_owned(),
+            min_gap: 0,
+            palette: None,
+        }
+    }
+}
+
+impl BuildOptions {
+    /// Checks that the options make sense together, before a build finds out
+    /// the hard way
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(Error::Config(message));
+
+        if self.min_radius == 0 {
+            return invalid("min-radius must be at least 1".to_owned());
+        }
+        if self.min_radius > self.max_radius {
+            return invalid(format!(
+                "min-radius ({}) is larger than max-radius ({})",
+                self.min_radius, self.max_radius
+            ));
+        }
+        // the radius shrinks by this fraction of itself; 1 or more would take
+        // it to zero or below in one step
+        if !(self.radius_step > 0.0 && self.radius_step < 1.0) {
+            return invalid(format!(
+                "radius-step ({}) must be between 0 and 1, exclusive",
+                self.radius_step
+            ));
+        }
+        for (name, value) in [
+            ("radius-shrink-threshold", self.radius_shrink_threshold),
+            ("similarity-threshold", self.similarity_threshold),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return invalid(format!("{} ({}) must be between 0 and 1", name, value));
+            }
+        }
+        if self.radius_attempt_limit == 0 {
+            return invalid("radius-attempt-limit must be at least 1".to_owned());
+        }
+        // each level halves the one before by shifting coordinates; past this
+        // the levels are a pixel across and the shifts overflow
+        if !(1..=MAX_PYRAMID_LEVELS).contains(&self.pyramid_levels) {
+            return invalid(format!(
+                "pyramid-levels ({}) must be between 1 and {}",
+                self.pyramid_levels, MAX_PYRAMID_LEVELS
+            ));
+        }
+        if self.pitch == 0 {
+            return invalid("pitch must be at least 1".to_owned());
+        }
+        // without a palette each cell gets its exact color, leaving no error
+        // to carry on
+        if self.dither && self.palette.is_none() {
+            return invalid("dither needs a palette to dither to".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// These options, with any set in a TOML or JSON config file (told apart
+    /// by extension) taking their place
+    pub fn with_file(&self, path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+
+        let values = if path.ends_with(".toml") {
+            toml::from_str::<toml::Table>(&contents)
+                .map_err(|e| {
+                    // toml reports where, but not on which line
+                    let offset = e.span().map_or(0, |s| s.start);
+                    let line = contents[..offset].matches('\n').count() + 1;
+                    Error::parse(path, line, e.message())
+                })
+                .and_then(|table| {
+                    serde_json::to_value(table).map_err(|e| Error::Config(e.to_string()))
+                })?
+        } else if path.ends_with(".json") {
+            serde_json::from_str(&contents).map_err(|e| Error::parse(path, e.line(), e))?
+        } else {
+            return Err(Error::Config(format!(
+                "{}: config files must be .toml or .json",
+                path
+            )));
+        };
+
+        let serde_json::Value::Object(values) = values else {
+            return Err(Error::parse(path, 1, "expected a table of options"));
+        };
+        self.merge(values)
+            .map_err(|e| Error::Config(format!("{}: {}", path, e)))
+    }
+
+    /// Writes the options as a config file `with_file` can read back, TOML or
+    /// JSON by extension
+    pub fn write(&self, path: &str) -> Result<()> {
+        let contents = if path.ends_with(".toml") {
+            // unset options are left out, since TOML has no null
+            let serde_json::Value::Object(mut values) = output::value(self) else {
+                unreachable!("options serialize to a map");
+            };
+            values.retain(|_, value| !value.is_null());
+            toml::to_string(&values).map_err(|e| Error::Config(e.to_string()))?
+        } else if path.ends_with(".json") {
+            serde_json::to_string_pretty(self).unwrap() + "\n"
+        } else {
+            return Err(Error::Config(format!(
+                "{}: config files must be .toml or .json",
+                path
+            )));
+        };
+
+        std::fs::write(path, contents).map_err(|e| Error::io(path, e))
+    }
+
+    /// These options, with the named ones (long flag names, like
+    /// "max-radius") taken from `overrides` instead
+    pub fn with_overrides(&self, overrides: &Self, names: &[String]) -> Self {
+        let serde_json::Value::Object(overrides) = serde_json::to_value(overrides).unwrap() else {
+            unreachable!("options serialize to a map");
+        };
+        let values = overrides
+            .into_iter()
+            .filter(|(name, _)| names.contains(name))
+            .collect();
+
+        // the values came from a BuildOptions, so they always fit back in one
+        self.merge(values).unwrap()
+    }
+
+    fn merge(
+        &self,
+        values: serde_json::Map<String, serde_json::Value>,
+    ) -> serde_json::Result<Self> {
+        let mut merged = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(fields) = &mut merged {
+            fields.extend(values);
+        }
+        serde_json::from_value(merged)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+pub struct RenderConfig {
+    /// Path to the input .smt file
+    #[cfg_attr(feature = "cli", arg(short = 'i', long))]
+    pub input: String,
+
+    /// Path to the output SVG file (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 's', long))]
+    pub svg: Option<String>,
+
+    /// Path to the output PNG file (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'p', long))]
+    pub png: Option<String>,
+
+    /// Prune with an occlusion-aware sequential pass that never changes the
+    /// rendered image (slower than the default parallel prune)
+    #[cfg_attr(feature = "cli", arg(short = 'c', long))]
+    pub precise: bool,
+
+    /// Recompute shape colors to best match this reference image over the
+    /// pixels each shape ends up showing
+    #[cfg_attr(feature = "cli", arg(short = 'f', long))]
+    pub refit: Option<String>,
+
+    /// Path to a false-color PNG of which shape owns each pixel, before any
+    /// are pruned (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'd', long))]
+    pub ids: Option<String>,
+
+    /// Remap colors to a palette (hex colors, a GIMP .gpl file, or "auto N");
+    /// swaps entries one-to-one if the file recorded a palette of the same size
+    #[cfg_attr(feature = "cli", arg(short = 'm', long))]
+    pub remap: Option<String>,
+
+    /// Map colors by luminance onto a gradient of hex colors, dark to light
+    /// (e.g. "#2b1d0e,#f3e3c3" for sepia)
+    #[cfg_attr(feature = "cli", arg(short = 'g', long))]
+    pub gradient_map: Option<String>,
+
+    /// Rotate hues by this many degrees
+    #[cfg_attr(feature = "cli", arg(short = 'u', long))]
+    pub hue_shift: Option<f32>,
+
+    /// Convert colors to grayscale
+    #[cfg_attr(feature = "cli", arg(short = 'y', long))]
+    pub grayscale: bool,
+
+    /// Invert colors
+    #[cfg_attr(feature = "cli", arg(short = 'n', long))]
+    pub invert: bool,
+
+    /// For CMYK files, path prefix for per-plate SVG and PDF files; writes
+    /// PREFIX-cyan.svg, PREFIX-cyan.pdf and so on (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'l', long))]
+    pub plates: Option<String>,
+
+    /// Path to write an SVG of pen strokes for a plotter, one Inkscape
+    /// layer per color (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'v', long))]
+    pub plotter_svg: Option<String>,
+
+    /// Path to write HPGL plotter output, one pen per color (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'e', long))]
+    pub hpgl: Option<String>,
+
+    /// Path to write G-code plotter output, pausing for a pen change between
+    /// colors (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'k', long))]
+    pub gcode: Option<String>,
+
+    /// Path to write a DXF drawing for laser cutting or CNC, one layer per
+    /// color (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'x', long))]
+    pub dxf: Option<String>,
+
+    /// Width of the cut in DXF output, in millimeters; radii are grown by
+    /// half of it so cut out parts come out at full size
+    #[cfg_attr(feature = "cli", arg(short = 'z', long, default_value_t = 0.0))]
+    pub kerf: f32,
+
+    /// Width of the image in plotter and DXF output, in millimeters
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            short = 'w',
+            long,
+            visible_alias = "plot-width",
+            default_value_t = 200.0
+        )
+    )]
+    pub physical_width: f32,
+
+    /// Width of the plotter's pen, in millimeters
+    #[cfg_attr(feature = "cli", arg(short = 'b', long, default_value_t = 0.5))]
+    pub pen_width: f32,
+
+    /// How the plotter fills in circles
+    #[cfg_attr(feature = "cli", arg(short = 'a', long, value_enum, default_value_t = Hatch::Concentric))]
+    pub hatch: Hatch,
+
+    /// Pens for plotter output (hex colors, a GIMP .gpl file, or "auto N");
+    /// defaults to the palette the file was built with. Shapes are drawn with
+    /// the nearest pen, or left as paper if the background is nearer.
+    #[cfg_attr(feature = "cli", arg(short = 'j', long))]
+    pub pens: Option<String>,
+}
+
+impl RenderConfig {
+    /// Checks for settings that can't make sense, whatever the file
+    pub fn validate(&self) -> Result<()> {
+        // a negative kerf would shrink parts, and can take radii below zero
+        if self.kerf < 0.0 || self.kerf.is_nan() {
+            return Err(Error::Config(format!(
+                "kerf ({}) can't be negative",
+                self.kerf
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+pub struct BatchConfig {
+    /// Directory of images, or a glob pattern such as "shoot/*.jpg" (quoted,
+    /// so the shell doesn't expand it)
+    #[cfg_attr(feature = "cli", arg(short = 'i', long))]
+    pub input: String,
+
+    /// Directory to write outputs to; created if missing
+    #[cfg_attr(feature = "cli", arg(short = 'o', long))]
+    pub output_dir: String,
+
+    /// Name of each raw output, where {stem} is the image's file name
+    /// without its extension and {ext} is its extension
+    #[cfg_attr(feature = "cli", arg(short = 'x', long, default_value = "{stem}.smt"))]
+    pub raw: String,
+
+    /// Name of each SVG output, like --raw
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub svg: Option<String>,
+
+    /// Name of each animated HTML page, like --raw
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub html: Option<String>,
+
+    /// Name of each built image, like --raw
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub png: Option<String>,
+
+    /// Number of images to build at once [default: one per CPU]
+    #[cfg_attr(feature = "cli", arg(short = 'j', long))]
+    pub jobs: Option<usize>,
+
+    /// Rebuild images even if their outputs are newer than they are
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub force: bool,
+
+    /// Start from a preset's settings; a config file and flags override them
+    #[cfg_attr(feature = "cli", arg(long, value_enum))]
+    pub preset: Option<Preset>,
+
+    /// Path to a TOML or JSON file of settings, as for build
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub config: Option<String>,
+
+    #[cfg_attr(feature = "cli", command(flatten))]
+    pub options: BuildOptions,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+#[cfg_attr(feature = "cli", command(group(
+    ArgGroup::new("budget")
+        .required(true)
+        .multiple(true)
+        .args(["target", "max_error"])
+)))]
+pub struct SimplifyConfig {
+    /// Path to the input .smt file
+    #[cfg_attr(feature = "cli", arg(short = 'i', long))]
+    pub input: String,
+
+    /// Path to the simplified .smt file (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'o', long))]
+    pub output: String,
+
+    /// Keep at most this many shapes
+    #[cfg_attr(feature = "cli", arg(short = 't', long))]
+    pub target: Option<usize>,
+
+    /// Stop before the mean per-channel error (0-255) exceeds this
+    #[cfg_attr(feature = "cli", arg(short = 'e', long))]
+    pub max_error: Option<f32>,
+
+    /// Measure fidelity against this image instead of the full render
+    #[cfg_attr(feature = "cli", arg(short = 'r', long))]
+    pub reference: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+pub struct InspectConfig {
+    /// Path to the input .smt file
+    #[cfg_attr(feature = "cli", arg(short = 'i', long))]
+    pub input: String,
+
+    /// Also measure the render's PSNR and SSIM against this image
+    #[cfg_attr(feature = "cli", arg(short = 'r', long))]
+    pub reference: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+pub struct CompareConfig {
+    /// Path to the first .smt file
+    #[cfg_attr(feature = "cli", arg(short = 'a', long))]
+    pub first: String,
+
+    /// Path to the second .smt file
+    #[cfg_attr(feature = "cli", arg(short = 'b', long))]
+    pub second: String,
+
+    /// Score both renders against this image, rather than only each other
+    #[cfg_attr(feature = "cli", arg(short = 'r', long))]
+    pub reference: Option<String>,
+
+    /// Path to a PNG showing where the renders differ (will overwrite). With
+    /// a reference, green marks where the first is closer to it and magenta
+    /// where the second is.
+    #[cfg_attr(feature = "cli", arg(short = 'd', long))]
+    pub diff: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+pub struct TuneConfig {
+    /// Path to the input image file
+    #[cfg_attr(feature = "cli", arg(short = 'i', long))]
+    pub input: String,
+
+    /// Path to write the recommended settings to, as a TOML or JSON config
+    /// file for build (will overwrite)
+    #[cfg_attr(feature = "cli", arg(short = 'o', long))]
+    pub output: Option<String>,
+
+    /// Downscale the input until its longer side is at most this many pixels,
+    /// so each trial build is quick; radii, pitch and gaps scale with it
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 200))]
+    pub size: u32,
+
+    /// How to pick the settings to try
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = Search::Grid))]
+    pub search: Search,
+
+    /// Number of settings to try in a random search
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 20))]
+    pub samples: usize,
+
+    /// Seed for a random search, so a run can be repeated
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub seed: Option<u64>,
+
+    /// Start from a preset's settings; a config file and flags override them
+    #[cfg_attr(feature = "cli", arg(long, value_enum))]
+    pub preset: Option<Preset>,
+
+    /// Path to a TOML or JSON file of settings, as for build
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub config: Option<String>,
+
+    /// Settings to tune around. The shrink threshold, radius step, attempt
+    /// limit and similarity threshold are searched over; the rest are kept.
+    #[cfg_attr(feature = "cli", command(flatten))]
+    pub options: BuildOptions,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn rejects_options_that_cant_build() {
+        assert!(BuildOptions::default().validate().is_ok());
+        for preset in [Preset::FastPreview, Preset::PrintQuality, Preset::Stipple] {
+            assert!(preset.options().validate().is_ok());
+        }
+
+        let invalid = [
+            BuildOptions {
+                min_radius: 10,
+                max_radius: 5,
+                ..Default::default()
+            },
+            BuildOptions {
+                radius_step: 0.0,
+                ..Default::default()
+            },
+            BuildOptions {
+                radius_step: 1.0,
+                ..Default::default()
+            },
+            BuildOptions {
+                similarity_threshold: -0.1,
+                ..Default::default()
+            },
+            BuildOptions {
+                pyramid_levels: 0,
+                ..Default::default()
+            },
+            BuildOptions {
+                pyramid_levels: 40,
+                ..Default::default()
+            },
+            BuildOptions {
+                mode: BuildMode::Grid,
+                dither: true,
+                ..Default::default()
+            },
+        ];
+        for options in invalid {
+            assert!(matches!(options.validate(), Err(Error::Config(_))));
+        }
+    }
+
+    #[test]
+    fn layers_files_and_flags_over_presets() {
+        let path = Scratch::new("options.toml");
+        std::fs::write(path.path(), "max-radius = 30\nmode = \"pack\"\n").unwrap();
+
+        let flags = BuildOptions {
+            max_radius: 40,
+            min_radius: 2,
+            ..Default::default()
+        };
+        let options = Preset::FastPreview
+            .options()
+            .with_file(path.as_str())
+            .unwrap()
+            .with_overrides(&flags, &["min-radius".to_owned()]);
+
+        assert_eq!(options.max_radius, 30);
+        assert_eq!(options.min_radius, 2);
+        assert_eq!(options.mode, BuildMode::Pack);
+        assert_eq!(options.pyramid_levels, 3);
+    }
+
+    #[test]
+    fn writes_files_it_can_read_back() {
+        let options = BuildOptions {
+            max_radius: 60,
+            radius_step: 0.25,
+            mode: BuildMode::Pack,
+            ..Default::default()
+        };
+
+        for name in ["written.toml", "written.json"] {
+            let path = Scratch::new(name);
+            options.write(path.as_str()).unwrap();
+            let read = BuildOptions::default().with_file(path.as_str()).unwrap();
+
+            assert_eq!(format!("{:?}", read), format!("{:?}", options));
+        }
+    }
+}
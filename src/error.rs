@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// Everything that can go wrong building, rendering or simplifying. Each
+/// variant carries enough context (usually the file involved) to make sense
+/// on its own when printed.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// An image couldn't be decoded or encoded
+    Image {
+        path: String,
+        source: image::ImageError,
+    },
+    /// A file was readable but its contents weren't; `line` is 1-based
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    /// Options that don't make sense, alone or together
+    Config(String),
+    /// The other end of an update channel went away mid-build
+    ChannelClosed,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn io(path: &str, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub(crate) fn image(path: &str, source: image::ImageError) -> Self {
+        Self::Image {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(path: &str, line: usize, message: impl fmt::Display) -> Self {
+        Self::Parse {
+            path: path.to_owned(),
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "{}: {}", path, source),
+            Self::Image { path, source } => write!(f, "{}: {}", path, source),
+            Self::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}, line {}: {}", path, line, message),
+            Self::Config(message) => write!(f, "{}", message),
+            Self::ChannelClosed => write!(f, "the build's update channel was closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Image { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{Canvas, Circle, Region};
+
+/// Per-pixel error between a reference and a working canvas, in the same
+/// RGB-only measure as `Canvas::delta`. Keeps a running total and a 2D Fenwick
+/// tree of the errors so that both the whole-image delta and the delta of any
+/// region are available without rescanning pixels. Only pixels a committed
+/// shape touched need to be refreshed.
+///
+/// A summed-area table would answer region queries in O(1), but every pixel a
+/// shape changes would then have to be carried into each entry below and to
+/// the right of it, up to the whole image per shape. The tree answers a region
+/// query in O(log w * log h) and updates a pixel in the same, so committing a
+/// shape stays proportional to its area.
+pub struct ErrorMap {
+    width: usize,
+    height: usize,
+    // per-pixel error; at most 3 * 255 so u16 is plenty
+    errors: Vec<u16>,
+    // 1-based Fenwick tree, (width + 1) * (height + 1) entries
+    tree: Vec<u64>,
+    total: usize,
+}
+
+impl ErrorMap {
+    pub fn new(reference: &Canvas, current: &Canvas) -> Self {
+        let width = reference.width() as usize;
+        let height = reference.height() as usize;
+
+        let reference_bytes = reference.img.as_bytes();
+        let current_bytes = current.img.as_bytes();
+
+        let errors: Vec<u16> = (0..width * height)
+            .map(|i| Self::error_at(reference_bytes, current_bytes, i))
+            .collect();
+
+        let total = errors.iter().map(|e| *e as usize).sum();
+
+        // build the tree in linear time: seed it with the raw values, then push
+        // each node into its parent along rows, and then along columns
+        let stride = width + 1;
+        let mut tree = vec![0u64; stride * (height + 1)];
+        for y in 0..height {
+            for x in 0..width {
+                tree[(y + 1) * stride + x + 1] = errors[y * width + x] as u64;
+            }
+        }
+        for y in 1..=height {
+            for x in 1..=width {
+                let parent = x + Self::low_bit(x);
+                if parent <= width {
+                    tree[y * stride + parent] += tree[y * stride + x];
+                }
+            }
+        }
+        for y in 1..=height {
+            let parent = y + Self::low_bit(y);
+            if parent <= height {
+                for x in 1..=width {
+                    tree[parent * stride + x] += tree[y * stride + x];
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            errors,
+            tree,
+            total,
+        }
+    }
+
+    fn low_bit(i: usize) -> usize {
+        i & i.wrapping_neg()
+    }
+
+    fn error_at(reference: &[u8], current: &[u8], index: usize) -> u16 {
+        let offset = index * 4;
+        (0..3)
+            .map(|c| Canvas::byte_delta(reference[offset + c], current[offset + c]) as u16)
+            .sum()
+    }
+
+    /// Sum of the error over the whole image
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Recomputes the error of every pixel within the region; call this after
+    /// the current canvas changed inside it.
+    pub fn update(&mut self, reference: &Canvas, current: &Canvas, region: &Region) {
+        let (min_x, min_y, max_x, max_y) = self.bounds(region);
+
+        let reference_bytes = reference.img.as_bytes();
+        let current_bytes = current.img.as_bytes();
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let index = y * self.width + x;
+                let old = self.errors[index];
+                let new = Self::error_at(reference_bytes, current_bytes, index);
+
+                if old != new {
+                    self.errors[index] = new;
+                    self.total = self.total + new as usize - old as usize;
+                    self.add(x + 1, y + 1, new as i64 - old as i64);
+                }
+            }
+        }
+    }
+
+    /// Sum of the error within the region, clipped to the image
+    pub fn region_sum(&self, region: &Region) -> usize {
+        let (min_x, min_y, max_x, max_y) = self.bounds(region);
+
+        let sum = self.prefix_sum(max_x, max_y) + self.prefix_sum(min_x, min_y)
+            - self.prefix_sum(min_x, max_y)
+            - self.prefix_sum(max_x, min_y);
+
+        sum as usize
+    }
+
+    /// Sum of the error within the region as it would be with the circle drawn
+    /// over the current canvas. Only the circle's own pixels are visited; the
+    /// rest of the region keeps its current error.
+    pub fn region_sum_with_circle(
+        &self,
+        reference: &Canvas,
+        region: &Region,
+        circle: &Circle,
+    ) -> usize {
+        let (min_x, min_y, max_x, max_y) = self.bounds(region);
+        let reference_bytes = reference.img.as_bytes();
+        let color = [circle.r, circle.g, circle.b];
+
+        let mut sum = self.region_sum(region) as i64;
+        for (y, first, last) in circle.rows() {
+            if y < min_y as i64 || y >= max_y as i64 {
+                continue;
+            }
+            let first = first.max(min_x as i64) as usize;
+            let last = (last + 1).min(max_x as i64).max(first as i64) as usize;
+
+            for x in first..last {
+                let index = y as usize * self.width + x;
+                let offset = index * 4;
+                let new: usize = (0..3)
+                    .map(|c| Canvas::byte_delta(reference_bytes[offset + c], color[c]))
+                    .sum();
+                sum += new as i64 - self.errors[index] as i64;
+            }
+        }
+
+        sum as usize
+    }
+
+    // clips a region to the image, as half-open pixel ranges
+    fn bounds(&self, region: &Region) -> (usize, usize, usize, usize) {
+        let min_x = (region.real_origin_x() as usize).min(self.width);
+        let min_y = (region.real_origin_y() as usize).min(self.height);
+        let max_x = (min_x + region.real_width() as usize).min(self.width);
+        let max_y = (min_y + region.real_height() as usize).min(self.height);
+
+        (min_x, min_y, max_x, max_y)
+    }
+
+    fn add(&mut self, x: usize, y: usize, value: i64) {
+        let stride = self.width + 1;
+        let mut j = y;
+        while j <= self.height {
+            let mut i = x;
+            while i <= self.width {
+                let node = &mut self.tree[j * stride + i];
+                *node = (*node as i64 + value) as u64;
+                i += Self::low_bit(i);
+            }
+            j += Self::low_bit(j);
+        }
+    }
+
+    // sum of all pixels with x < max_x and y < max_y
+    fn prefix_sum(&self, max_x: usize, max_y: usize) -> u64 {
+        let stride = self.width + 1;
+        let mut sum = 0;
+        let mut j = max_y;
+        while j > 0 {
+            let mut i = max_x;
+            while i > 0 {
+                sum += self.tree[j * stride + i];
+                i -= Self::low_bit(i);
+            }
+            j -= Self::low_bit(j);
+        }
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Circle;
+    use image::Rgba;
+
+    fn reference() -> Canvas {
+        let mut canvas = Canvas::new(37, 23);
+        canvas.draw_circle(&Circle::new(10, 10, 8, Rgba([200, 40, 90, 255])));
+        canvas.draw_circle(&Circle::new(30, 15, 12, Rgba([20, 140, 250, 255])));
+        canvas
+    }
+
+    #[test]
+    fn matches_full_delta() {
+        let reference = reference();
+        let mut current = Canvas::new(37, 23);
+        let mut errors = ErrorMap::new(&reference, &current);
+        assert_eq!(errors.total(), reference.delta(&current.img));
+
+        let circle = Circle::new(12, 9, 6, Rgba([190, 50, 80, 255]));
+        current.draw_circle(&circle);
+        errors.update(
+            &reference,
+            &current,
+            &Region::new(circle.x, circle.y, circle.radius + 1),
+        );
+        assert_eq!(errors.total(), reference.delta(&current.img));
+    }
+
+    #[test]
+    fn region_sums_match_crops() {
+        let reference = reference();
+        let mut current = Canvas::new(37, 23);
+        current.draw_circle(&Circle::new(5, 20, 9, Rgba([90, 90, 90, 255])));
+        let errors = ErrorMap::new(&reference, &current);
+
+        for (x, y, radius) in [(0, 0, 3), (18, 11, 7), (36, 22, 15), (4, 19, 30)] {
+            let region = Region::new(x, y, radius);
+            let expected = reference
+                .section(&region)
+                .delta(&current.section(&region).img);
+            assert_eq!(errors.region_sum(&region), expected);
+        }
+    }
+
+    #[test]
+    fn predicts_the_error_of_a_circle() {
+        let reference = reference();
+        let mut current = Canvas::new(37, 23);
+        current.draw_circle(&Circle::new(5, 20, 9, Rgba([90, 90, 90, 255])));
+        let errors = ErrorMap::new(&reference, &current);
+
+        for (x, y, radius) in [(0, 0, 3), (18, 11, 7), (36, 22, 15), (4, 19, 30)] {
+            let circle = Circle::new(x, y, radius, Rgba([180, 60, 100, 255]));
+            let region = Region::new(x, y, radius + 1);
+            let mut drawn = current.clone();
+            drawn.draw_circle(&circle);
+
+            let expected = reference
+                .section(&region)
+                .delta(&drawn.section(&region).img);
+            assert_eq!(
+                errors.region_sum_with_circle(&reference, &region, &circle),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn leaves_alpha_out() {
+        // a half transparent reference; drawn shapes are always opaque
+        let mut reference = reference();
+        for pixel in reference.img.as_mut_rgba8().unwrap().pixels_mut() {
+            pixel[3] = 128;
+        }
+        let mut current = Canvas::new(37, 23);
+        let circle = Circle::new(10, 10, 8, Rgba([200, 40, 90, 255]));
+        current.draw_circle(&circle);
+
+        let errors = ErrorMap::new(&reference, &current);
+        assert_eq!(errors.total(), reference.delta(&current.img));
+
+        // the circle matches the reference exactly, alpha aside
+        let region = Region::new(10, 10, 3);
+        assert_eq!(errors.region_sum(&region), 0);
+        assert_eq!(
+            reference
+                .section(&region)
+                .delta(&current.section(&region).img),
+            0
+        );
+    }
+}
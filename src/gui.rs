@@ -2,24 +2,51 @@ use std::sync::mpsc::channel;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 
-use crate::builder::{Builder, BuilderCommand, BuilderUpdate, Stats};
-use crate::{BuildConfig, Canvas};
+use crate::BuildConfig;
 use eframe::{egui, epaint::ColorImage, App, CreationContext, NativeOptions};
 use image::DynamicImage;
+use sediment::builder::{Builder, Stats};
+use sediment::observer::{BuilderUpdate, Channel};
+use sediment::{output::Output, Canvas, Result};
 
 static PREVIEW_TEXTURE_ID: &str = "preview-image";
 static REFERENCE_TEXTURE_ID: &str = "reference-image";
 
-pub fn run(build_config: BuildConfig) {
+pub enum BuilderCommand {
+    Start,
+    Quit,
+}
+
+pub fn run(build_config: BuildConfig) -> Result<()> {
     let (builder_update_tx, builder_update_rx) = channel();
     let (builder_command_tx, builder_command_rx) = channel();
 
     let builder_config = build_config.clone();
     let gui_config = build_config.clone();
 
+    // fail before opening a window if the input or options are bad
+    let reference = Canvas::open(&builder_config.input)?;
+    let log = crate::progress_log(&builder_config, &reference)?;
+    let interval = crate::update_interval(&builder_config)?;
+    let builder = Builder::new(reference, builder_config.options.clone())?;
+
     thread::spawn(move || {
-        let mut builder = Builder::new(builder_update_tx, builder_config);
-        builder.interactive(builder_command_rx);
+        let mut builder = builder
+            .with_observer((Channel::new(builder_update_tx).with_previews(), log))
+            .with_progress(interval);
+
+        // build each time we're told to start, until told to quit
+        while let Ok(BuilderCommand::Start) = builder_command_rx.recv() {
+            // the window closing ends the build early
+            let Ok(shapes) = builder.run() else {
+                return;
+            };
+            if let Err(e) =
+                crate::write_outputs(Output::default(), &builder_config, builder.image(), &shapes)
+            {
+                eprintln!("Error: {}", e);
+            }
+        }
     });
 
     let options = NativeOptions {
@@ -39,6 +66,8 @@ pub fn run(build_config: BuildConfig) {
             ))
         }),
     );
+
+    Ok(())
 }
 
 pub struct SedimentApp {
@@ -140,12 +169,13 @@ impl App for SedimentApp {
         ctx.request_repaint(); // continuous repainting
 
         if ctx.input(|i| i.key_released(egui::Key::Escape)) {
-            self.builder_command_tx.send(BuilderCommand::Quit).unwrap();
+            // the builder may already have stopped
+            let _ = self.builder_command_tx.send(BuilderCommand::Quit);
             std::process::exit(0);
         }
 
         if ctx.input(|i| i.key_released(egui::Key::R)) {
-            self.builder_command_tx.send(BuilderCommand::Start).unwrap();
+            let _ = self.builder_command_tx.send(BuilderCommand::Start);
         }
 
         // handle any messages that may have come in from the builder
@@ -0,0 +1,169 @@
+use crate::{palette::Palette, Canvas, Circle};
+use image::Rgba;
+
+/// One ink of a four-color separation
+pub struct Plate {
+    pub name: &'static str,
+    pub ink: Rgba<u8>,
+    /// screen angle, in degrees
+    pub angle: f32,
+}
+
+/// Process inks in print order, at the conventional screen angles
+pub const PLATES: [Plate; 4] = [
+    Plate {
+        name: "cyan",
+        ink: Rgba([0, 255, 255, 255]),
+        angle: 15.0,
+    },
+    Plate {
+        name: "magenta",
+        ink: Rgba([255, 0, 255, 255]),
+        angle: 75.0,
+    },
+    Plate {
+        name: "yellow",
+        ink: Rgba([255, 255, 0, 255]),
+        angle: 0.0,
+    },
+    Plate {
+        name: "black",
+        ink: Rgba([0, 0, 0, 255]),
+        angle: 45.0,
+    },
+];
+
+/// The four process inks as a palette, in plate order
+pub fn palette() -> Palette {
+    Palette::new(PLATES.iter().map(|p| p.ink).collect())
+}
+
+/// Naive RGB to CMYK separation, with full black generation. Returns the ink
+/// coverage (0.0 to 1.0) of each plate, in plate order.
+pub fn separate(color: Rgba<u8>) -> [f32; 4] {
+    let r = color[0] as f32 / 255.0;
+    let g = color[1] as f32 / 255.0;
+    let b = color[2] as f32 / 255.0;
+
+    let k = 1.0 - r.max(g).max(b);
+    if k >= 1.0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+
+    [
+        (1.0 - r - k) / (1.0 - k),
+        (1.0 - g - k) / (1.0 - k),
+        (1.0 - b - k) / (1.0 - k),
+        k,
+    ]
+}
+
+/// The separation of the reference for one plate, as a film would show it:
+/// black where the plate puts down full ink, white where it puts down none
+pub fn separation(reference: &Canvas, plate_index: usize) -> Canvas {
+    let mut output = reference.clone();
+
+    for pixel in output.img.as_mut_rgba8().unwrap().pixels_mut() {
+        let coverage = separate(*pixel)[plate_index];
+        let value = (255.0 * (1.0 - coverage)).round() as u8;
+        *pixel = Rgba([value, value, value, 255]);
+    }
+
+    output
+}
+
+/// Centers of a halftone screen's cells over an image: a square lattice with
+/// the given pitch (in pixels), rotated to the given angle (in degrees) about
+/// the image's center
+pub fn screen(width: u32, height: u32, pitch: u32, angle: f32) -> Vec<(u32, u32)> {
+    let (width, height) = (width as f32, height as f32);
+    let pitch = pitch.max(1) as f32;
+
+    // walk the rotated lattice far enough out to cover every corner
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let center_x = width / 2.0;
+    let center_y = height / 2.0;
+    let reach = (width.hypot(height) / pitch / 2.0).ceil() as i32 + 1;
+
+    let mut points = vec![];
+    for j in -reach..=reach {
+        for i in -reach..=reach {
+            let x = center_x + pitch * (i as f32 * cos - j as f32 * sin);
+            let y = center_y + pitch * (i as f32 * sin + j as f32 * cos);
+            if x >= 0.0 && y >= 0.0 && x < width && y < height {
+                points.push((x as u32, y as u32));
+            }
+        }
+    }
+
+    points
+}
+
+/// Groups circles by the plate recorded for each of them
+pub fn group_plates(circles: &[Circle], plates: &[usize]) -> [Vec<Circle>; 4] {
+    let mut grouped: [Vec<Circle>; 4] = Default::default();
+
+    for (c, plate) in circles.iter().zip(plates) {
+        grouped[*plate].push(*c);
+    }
+
+    grouped
+}
+
+/// Previews plates as printed: each ink multiplies into white paper
+pub fn composite(
+    plates: &[Vec<Circle>; 4],
+    inks: &[Rgba<u8>; 4],
+    width: u32,
+    height: u32,
+) -> Canvas {
+    let white = Rgba([255, 255, 255, 255]);
+    let mut output = Canvas::filled(width, height, white);
+
+    for (circles, ink) in plates.iter().zip(inks.iter()) {
+        let mut layer = Canvas::filled(width, height, white);
+        for c in circles {
+            layer.draw_circle(&Circle::new(c.x, c.y, c.radius, *ink));
+        }
+
+        let layer = layer.img.as_bytes().to_vec();
+        let out = output.img.as_mut_rgba8().unwrap();
+        for (o, l) in out.iter_mut().zip(layer.iter()) {
+            *o = ((*o as u16 * *l as u16) / 255) as u8;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_primaries() {
+        assert_eq!(separate(Rgba([255, 255, 255, 255])), [0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(separate(Rgba([0, 0, 0, 255])), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(separate(Rgba([0, 255, 255, 255])), [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(separate(Rgba([255, 0, 0, 255])), [0.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn separates_each_ink_onto_its_own_plate() {
+        let cyan = Canvas::filled(8, 8, Rgba([0, 255, 255, 255]));
+        let films = [0, 1, 2, 3].map(|plate| separation(&cyan, plate).mean_color());
+
+        assert_eq!(films[0], Rgba([0, 0, 0, 255]));
+        assert!(films[1..].iter().all(|f| *f == Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn screens_cover_the_image_at_the_pitch() {
+        for plate in PLATES {
+            let points = screen(64, 64, 8, plate.angle);
+            // 64 cells of 8x8, give or take the ones cut by the edges
+            assert!((56..=72).contains(&points.len()), "{}", points.len());
+            assert!(points.contains(&(32, 32)));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{Canvas, Circle};
+use image::{ImageBuffer, Luma, Rgba};
+
+/// Per-pixel index of the topmost circle, rasterized exactly like the color
+/// render. Pixels store the circle's position in the list plus one, so that
+/// zero can mean "background".
+pub struct IdBuffer {
+    ids: ImageBuffer<Luma<u32>, Vec<u32>>,
+}
+
+impl IdBuffer {
+    pub fn render(circles: &[Circle], width: u32, height: u32) -> Self {
+        let mut ids = ImageBuffer::new(width, height);
+
+        for (i, c) in circles.iter().enumerate() {
+            imageproc::drawing::draw_filled_circle_mut(
+                &mut ids,
+                (c.x as i32, c.y as i32),
+                c.radius as i32,
+                Luma([i as u32 + 1]),
+            );
+        }
+
+        Self { ids }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.ids.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.ids.height()
+    }
+
+    /// Position of the circle on top at the given pixel, or None for background
+    pub fn top(&self, x: u32, y: u32) -> Option<usize> {
+        match self.ids.get_pixel(x, y).0[0] {
+            0 => None,
+            id => Some((id - 1) as usize),
+        }
+    }
+
+    /// Raw ids in row order; zero is background, otherwise list position + 1
+    pub fn ids(&self) -> &[u32] {
+        self.ids.as_raw()
+    }
+
+    /// Pixel indices (row order) grouped by the circle on top of them. Entry
+    /// `i` holds the pixels owned by circle `i`; background pixels are left out.
+    pub fn pixels_by_circle(&self, circle_count: usize) -> Vec<Vec<u32>> {
+        let mut groups = vec![vec![]; circle_count];
+
+        for (pixel, id) in self.ids().iter().enumerate() {
+            if *id > 0 {
+                groups[(*id - 1) as usize].push(pixel as u32);
+            }
+        }
+
+        groups
+    }
+
+    /// Number of pixels each circle ends up showing
+    pub fn visible_counts(&self, circle_count: usize) -> Vec<usize> {
+        let mut counts = vec![0; circle_count];
+
+        for id in self.ids().iter().filter(|id| **id > 0) {
+            counts[(*id - 1) as usize] += 1;
+        }
+
+        counts
+    }
+
+    /// Positions of the circles that don't show a single pixel
+    pub fn hidden_shapes(&self, circle_count: usize) -> Vec<usize> {
+        self.visible_counts(circle_count)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, count)| *count == 0)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Paints each circle's visible pixels in an arbitrary but stable color,
+    /// with the background left black; handy for eyeballing overdraw.
+    pub fn false_color(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width(), self.height());
+        let pixels = canvas.img.as_mut_rgba8().unwrap();
+
+        for (pixel, id) in pixels.pixels_mut().zip(self.ids().iter()) {
+            if *id > 0 {
+                // scramble the id so neighbouring circles get distinct colors
+                let [r, g, b, _] = id.wrapping_mul(0x9E37_79B1).to_be_bytes();
+                *pixel = Rgba([r, g, b, 255]);
+            }
+        }
+
+        canvas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_visibility() {
+        let color = Rgba([10, 20, 30, 255]);
+        let circles = vec![
+            Circle::new(20, 20, 5, color),
+            Circle::new(20, 20, 15, color),
+            Circle::new(10, 10, 3, color),
+        ];
+        let ids = IdBuffer::render(&circles, 40, 40);
+
+        assert_eq!(ids.top(20, 20), Some(1));
+        assert_eq!(ids.top(10, 10), Some(2));
+        assert_eq!(ids.top(39, 39), None);
+        assert_eq!(ids.hidden_shapes(circles.len()), vec![0]);
+
+        let counts = ids.visible_counts(circles.len());
+        let background = ids.ids().iter().filter(|id| **id == 0).count();
+        assert_eq!(counts.iter().sum::<usize>() + background, 40 * 40);
+    }
+}
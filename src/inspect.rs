@@ -0,0 +1,310 @@
+use crate::{
+    id_buffer::IdBuffer, metrics, output::Output, palette::Palette, Canvas, Circle, InspectConfig,
+    Render, Result, ShapeList,
+};
+use image::{ImageBuffer, Luma, Rgba};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Most common colors listed in a report
+const TOP_COLORS: usize = 10;
+
+/// Widest bar in a text histogram, in characters
+const BAR_WIDTH: usize = 40;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Shapes with radii from `min` to `max`, inclusive
+#[derive(Serialize, Debug)]
+pub struct RadiusBucket {
+    pub min: u32,
+    pub max: u32,
+    pub shapes: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ColorCount {
+    pub color: String,
+    pub shapes: usize,
+}
+
+/// How closely the render matches a reference image
+#[derive(Serialize, Debug)]
+pub struct Fidelity {
+    pub psnr: f64,
+    pub ssim: f64,
+}
+
+/// What's in a raw file, measured without rendering it out
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub shapes: usize,
+    // the size of the image the shapes were built from, if recorded
+    pub declared_size: Option<Size>,
+    // the size a render comes out at: the furthest reach of any shape
+    pub inferred_size: Size,
+    // entries in the recorded palette, if any
+    pub palette_size: Option<usize>,
+    pub distinct_colors: usize,
+    // the most common colors, most common first
+    pub colors: Vec<ColorCount>,
+    // radii in power-of-two buckets, smallest first
+    pub radii: Vec<RadiusBucket>,
+    // fraction of the image's pixels under at least one shape
+    pub coverage: f64,
+    // pixels painted for every pixel covered; 1 means no shape overlaps another
+    pub overdraw: f64,
+    // shapes with no pixel left showing
+    pub hidden: usize,
+    pub fidelity: Option<Fidelity>,
+}
+
+impl Report {
+    /// Measures the shapes over the declared size (or the inferred one, if
+    /// none was recorded), and against the reference if there is one
+    pub fn new(shapes: &ShapeList, reference: Option<&Canvas>) -> Self {
+        let circles = &shapes.circles;
+        let inferred_size = Size {
+            width: Render::image_width(circles),
+            height: Render::image_height(circles),
+        };
+        let declared_size = shapes.size.map(|(width, height)| Size { width, height });
+        let size = declared_size.unwrap_or(inferred_size);
+
+        let ids = IdBuffer::render(circles, size.width, size.height);
+        let covered = ids.ids().iter().filter(|id| **id > 0).count();
+        let painted: usize = circles.iter().map(|c| Self::painted_pixels(c, size)).sum();
+        let pixels = (size.width as usize * size.height as usize).max(1);
+
+        let fidelity = reference.map(|reference| {
+            let render = Render::render_shapes_on(shapes, reference.width(), reference.height());
+            Fidelity {
+                psnr: metrics::psnr(reference, &render),
+                ssim: metrics::ssim(reference, &render),
+            }
+        });
+
+        let colors = Self::color_counts(circles);
+
+        Self {
+            shapes: circles.len(),
+            declared_size,
+            inferred_size,
+            palette_size: shapes.palette.as_ref().map(|p| p.entries.len()),
+            distinct_colors: colors.len(),
+            colors: colors.into_iter().take(TOP_COLORS).collect(),
+            radii: Self::radius_buckets(circles),
+            coverage: covered as f64 / pixels as f64,
+            overdraw: painted as f64 / covered.max(1) as f64,
+            hidden: ids.hidden_shapes(circles.len()).len(),
+            fidelity,
+        }
+    }
+
+    // pixels a circle paints inside the image, rasterized like the render
+    fn painted_pixels(circle: &Circle, size: Size) -> usize {
+        let r = circle.radius as i64;
+        let side = (2 * r + 1) as u32;
+        let mut stamp = ImageBuffer::<Luma<u8>, Vec<u8>>::new(side, side);
+        imageproc::drawing::draw_filled_circle_mut(
+            &mut stamp,
+            (r as i32, r as i32),
+            r as i32,
+            Luma([1]),
+        );
+
+        stamp
+            .enumerate_pixels()
+            .filter(|(x, y, p)| {
+                let x = circle.x as i64 - r + *x as i64;
+                let y = circle.y as i64 - r + *y as i64;
+                p.0[0] > 0
+                    && (0..size.width as i64).contains(&x)
+                    && (0..size.height as i64).contains(&y)
+            })
+            .count()
+    }
+
+    fn color_counts(circles: &[Circle]) -> Vec<ColorCount> {
+        let mut counts: HashMap<Rgba<u8>, usize> = HashMap::new();
+        for c in circles {
+            *counts.entry(Rgba([c.r, c.g, c.b, 255])).or_default() += 1;
+        }
+
+        let mut counts: Vec<(Rgba<u8>, usize)> = counts.into_iter().collect();
+        // ties broken by color, so reports are stable
+        counts.sort_by_key(|(color, count)| (std::cmp::Reverse(*count), color.0));
+        counts
+            .into_iter()
+            .map(|(color, shapes)| ColorCount {
+                color: Palette::hex(color),
+                shapes,
+            })
+            .collect()
+    }
+
+    // bucket 0 holds radius 0; bucket n holds 2^(n-1) up to 2^n - 1
+    fn radius_buckets(circles: &[Circle]) -> Vec<RadiusBucket> {
+        let mut counts = [0; u32::BITS as usize + 1];
+        for c in circles {
+            counts[(u32::BITS - c.radius.leading_zeros()) as usize] += 1;
+        }
+
+        counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| RadiusBucket {
+                min: if bucket == 0 { 0 } else { 1 << (bucket - 1) },
+                max: if bucket == 0 {
+                    0
+                } else {
+                    ((1u64 << bucket) - 1) as u32
+                },
+                shapes: *count,
+            })
+            .collect()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        let size = |s: Size| format!("{}x{}", s.width, s.height);
+
+        writeln!(output, "shapes:    {}", self.shapes).unwrap();
+        match self.declared_size {
+            Some(declared) => writeln!(
+                output,
+                "size:      {} declared, {} inferred",
+                size(declared),
+                size(self.inferred_size)
+            ),
+            None => writeln!(
+                output,
+                "size:      {} inferred (none declared)",
+                size(self.inferred_size)
+            ),
+        }
+        .unwrap();
+        write!(output, "colors:    {} distinct", self.distinct_colors).unwrap();
+        if let Some(palette_size) = self.palette_size {
+            write!(output, ", palette of {}", palette_size).unwrap();
+        }
+        writeln!(output).unwrap();
+        writeln!(output, "coverage:  {:.1}%", self.coverage * 100.0).unwrap();
+        writeln!(output, "overdraw:  {:.2}x", self.overdraw).unwrap();
+        writeln!(output, "hidden:    {}", self.hidden).unwrap();
+        if let Some(fidelity) = &self.fidelity {
+            writeln!(output, "psnr:      {}", metrics::psnr_text(fidelity.psnr)).unwrap();
+            writeln!(output, "ssim:      {:.4}", fidelity.ssim).unwrap();
+        }
+
+        let most = self.radii.iter().map(|b| b.shapes).max().unwrap_or(1);
+        writeln!(output, "\nradii:").unwrap();
+        for bucket in &self.radii {
+            let range = if bucket.min == bucket.max {
+                bucket.min.to_string()
+            } else {
+                format!("{}-{}", bucket.min, bucket.max)
+            };
+            writeln!(
+                output,
+                "  {:>9}  {:>8}  {}",
+                range,
+                bucket.shapes,
+                "#".repeat((bucket.shapes * BAR_WIDTH).div_ceil(most))
+            )
+            .unwrap();
+        }
+
+        writeln!(output, "\nmost common colors:").unwrap();
+        for color in &self.colors {
+            writeln!(output, "  {}  {:>8}", color.color, color.shapes).unwrap();
+        }
+
+        output
+    }
+}
+
+/// The `inspect` subcommand: reports on a raw file
+pub struct Inspector {
+    config: InspectConfig,
+    shapes: ShapeList,
+    output: Output,
+}
+
+impl Inspector {
+    pub fn new(config: InspectConfig) -> Result<Self> {
+        let shapes = ShapeList::read(&config.input)?;
+        Ok(Self {
+            config,
+            shapes,
+            output: Output::default(),
+        })
+    }
+
+    /// Where progress and the result are reported
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let reference = match &self.config.reference {
+            Some(path) => Some(Canvas::open(path)?),
+            None => None,
+        };
+        let report = Report::new(&self.shapes, reference.as_ref());
+
+        self.output.report(&report, Report::to_text, &[]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_coverage_and_overdraw() {
+        let red = Rgba([255, 0, 0, 255]);
+        let shapes = ShapeList {
+            circles: vec![
+                Circle::new(10, 10, 4, red),
+                // entirely under the next one
+                Circle::new(30, 10, 2, Rgba([0, 0, 255, 255])),
+                Circle::new(30, 10, 6, red),
+            ],
+            size: Some((40, 20)),
+            ..Default::default()
+        };
+        let report = Report::new(&shapes, None);
+
+        assert_eq!(report.shapes, 3);
+        assert_eq!(
+            report.inferred_size,
+            Size {
+                width: 36,
+                height: 16
+            }
+        );
+        assert_eq!(report.hidden, 1);
+        assert_eq!(report.distinct_colors, 2);
+        assert_eq!(report.colors[0].color, "#ff0000");
+        assert_eq!(report.colors[0].shapes, 2);
+        assert!(report.coverage > 0.0 && report.coverage < 0.5);
+        assert!(report.overdraw > 1.0);
+
+        let radii: Vec<(u32, u32, usize)> = report
+            .radii
+            .iter()
+            .map(|b| (b.min, b.max, b.shapes))
+            .collect();
+        assert_eq!(radii, vec![(2, 3, 1), (4, 7, 2)]);
+    }
+}
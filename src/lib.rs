@@ -0,0 +1,110 @@
+#![warn(clippy::all)]
+
+pub mod batch;
+pub mod builder;
+mod canvas;
+mod circle;
+pub mod color_transform;
+pub mod compare;
+mod config;
+mod error;
+mod error_map;
+mod halftone;
+mod id_buffer;
+pub mod inspect;
+pub mod metrics;
+pub mod mosaic;
+pub mod observer;
+pub mod optimizer;
+pub mod output;
+pub mod palette;
+mod point_selector;
+mod pyramid;
+mod rate_meter;
+mod region;
+pub mod render;
+#[cfg(test)]
+mod scratch;
+pub mod shape_list;
+pub mod simplifier;
+mod spatial_index;
+pub mod tune;
+
+pub use builder::Builder;
+pub use canvas::Canvas;
+pub use circle::Circle;
+pub use config::{
+    BatchConfig, BuildMode, BuildOptions, CompareConfig, Format, Hatch, InspectConfig, Lattice,
+    Preset, RenderConfig, Search, SimplifyConfig, TuneConfig,
+};
+pub use error::{Error, Result};
+pub use id_buffer::IdBuffer;
+pub use optimizer::Optimizer;
+pub use region::Region;
+pub use render::Render;
+pub use shape_list::ShapeList;
+pub use spatial_index::SpatialIndex;
+
+use image::DynamicImage;
+
+/// Approximates an image with circles. This is a whole `sediment build` run
+/// on the calling thread, returning the shapes rather than writing them out;
+/// render them with `Render::render_svg` or `Render::render_raster_on`.
+///
+/// Fails if `options` are invalid or name a palette or color that can't be
+/// parsed.
+pub fn build(image: DynamicImage, options: &BuildOptions) -> Result<ShapeList> {
+    let mut builder = Builder::new(Canvas::from_image(image), options.clone())?;
+    builder.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[cfg(feature = "cli")]
+    #[derive(clap::Parser)]
+    struct Flags {
+        #[command(flatten)]
+        options: BuildOptions,
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn defaults_match_the_command_line() {
+        use clap::Parser;
+
+        let parsed = Flags::parse_from(["sediment"]).options;
+        assert_eq!(
+            format!("{:?}", parsed),
+            format!("{:?}", BuildOptions::default())
+        );
+    }
+
+    #[test]
+    fn builds_from_memory() {
+        let mut canvas = Canvas::new(64, 48);
+        canvas.draw_circle(&Circle::new(32, 24, 16, Rgba([255, 255, 255, 255])));
+
+        let options = BuildOptions {
+            max_radius: 20,
+            min_radius: 4,
+            ..Default::default()
+        };
+        let shapes = build(canvas.img, &options).unwrap();
+
+        assert!(!shapes.circles.is_empty());
+        assert!(shapes.circles.iter().all(|c| c.radius <= 20));
+    }
+
+    #[test]
+    fn reports_bad_options() {
+        let options = BuildOptions {
+            palette: Some("#12345".to_owned()),
+            ..Default::default()
+        };
+        let result = build(Canvas::new(8, 8).img, &options);
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+}
@@ -1,28 +1,41 @@
 #![warn(clippy::all)]
 
-mod builder;
-mod canvas;
-mod circle;
 mod gui;
-mod optimizer;
-mod point_selector;
-mod rate_meter;
-mod region;
-mod render;
 
-pub use canvas::Canvas;
-pub use circle::Circle;
-pub use region::Region;
-pub use render::Render;
+use sediment::{
+    batch::Batch,
+    compare::Comparer,
+    inspect::Inspector,
+    metrics, mosaic,
+    observer::{stats_json, JsonLines, Printer, ProgressLog},
+    output::{self, Output},
+    tune::Tuner,
+    BatchConfig, BuildMode, BuildOptions, Builder, Canvas, CompareConfig, Error, Format,
+    InspectConfig, Preset, RenderConfig, Result, ShapeList, SimplifyConfig, TuneConfig,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::process::ExitCode;
+use std::time::Duration;
 
-use builder::{Builder, BuilderUpdate, Stats};
-use std::sync::mpsc::channel;
-use std::thread;
-
-use clap::{Args, Parser, Subcommand};
+use clap::{
+    parser::ValueSource, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+};
 
 #[derive(Clone, Parser, Debug)]
 pub struct Config {
+    /// How to report: messages for people, or JSON lines on stdout of the
+    /// settings, progress, warnings, errors and a result with the outputs
+    /// written and how well they match
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Short for --format json
+    #[arg(long, global = true)]
+    json: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -33,9 +46,19 @@ pub enum Command {
     Build(BuildConfig),
     /// Render a sediment file to an image file
     Render(RenderConfig),
+    /// Reduce a sediment file to fewer shapes, trading away some fidelity
+    Simplify(SimplifyConfig),
+    /// Build every image in a directory, several at a time
+    Batch(BatchConfig),
+    /// Report on the shapes in a sediment file
+    Inspect(InspectConfig),
+    /// Measure two sediment files against each other, and a reference image
+    Compare(CompareConfig),
+    /// Try a range of build settings on a downscaled image and recommend some
+    Tune(TuneConfig),
 }
 
-#[derive(Args, Clone, Debug)]
+#[derive(Args, Serialize, Clone, Debug)]
 pub struct BuildConfig {
     /// Path to the input image file
     #[arg(short = 'i', long)]
@@ -49,102 +72,285 @@ pub struct BuildConfig {
     #[arg(short = 'x', long)]
     raw: Option<String>,
 
-    /// Maximum radius of the shapes to be placed
-    #[arg(short = 'r', long, default_value_t = 500)]
-    max_radius: u32,
+    /// Start from a preset's settings; a config file and flags override them
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
 
-    /// Minimum radius of the shapes to be placed
-    #[arg(short = 'm', long, default_value_t = 1)]
-    min_radius: u32,
+    /// Path to a TOML or JSON file of settings, named like the long flags
+    /// (e.g. max-radius = 200); flags given on the command line override them
+    #[arg(long)]
+    config: Option<String>,
 
-    /// Shrink the radius size when successes are lower than this rate
-    #[arg(short = 't', long, default_value_t = 0.2)]
-    radius_shrink_threshold: f32,
+    #[command(flatten)]
+    options: BuildOptions,
 
-    /// Amount to shrink the radius at each step
-    #[arg(short = 'p', long, default_value_t = 0.1)]
-    radius_step: f32,
+    /// In grid mode, path to write a CSV bill of materials with the number
+    /// of pieces of each color (will overwrite)
+    #[arg(short = 'u', long)]
+    bom: Option<String>,
 
-    /// Reduce the radius size after this many attempts are made
-    #[arg(short = 'a', long, default_value_t = 5000)]
-    radius_attempt_limit: usize,
+    /// Path to log the build's stats over time, as CSV (.csv) or JSON lines
+    /// (.jsonl): a row at each radius change, and every --progress-interval
+    /// seconds in between (will overwrite)
+    #[arg(long)]
+    progress_log: Option<String>,
 
-    /// Threshold for skipping shape placement
-    #[arg(short = 's', long, short, default_value_t = 0.9)]
-    similarity_threshold: f32,
+    /// Seconds between progress log rows within a radius
+    #[arg(long, default_value_t = 1.0)]
+    progress_interval: f32,
 
     /// Display a GUI to view progress
     #[arg(short = 'g', long)]
     gui: bool,
 }
 
-#[derive(Args, Clone, Debug)]
-pub struct RenderConfig {
-    /// Path to the input .smt file
-    #[arg(short = 'i', long)]
-    input: String,
+fn main() -> ExitCode {
+    let matches = Config::command().get_matches();
+    let config = Config::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let output = Output::new(if config.json {
+        Format::Json
+    } else {
+        config.format
+    });
 
-    /// Path to the output SVG file (will overwrite)
-    #[arg(short = 's', long)]
-    svg: Option<String>,
+    let result = match config.command {
+        Command::Build(mut build_config) => {
+            let flags = matches.subcommand_matches("build").unwrap();
+            let options = resolve_options(
+                build_config.preset,
+                build_config.config.as_deref(),
+                &build_config.options,
+                flags,
+            );
+            options.and_then(|options| {
+                build_config.options = options;
+                print_build_config(output, &build_config);
 
-    /// Path to the output PNG file (will overwrite)
-    #[arg(short = 'p', long)]
-    png: Option<String>,
-}
+                if build_config.gui && output.is_json() {
+                    output.warning("--gui doesn't report in JSON; building without it");
+                    headless_run(output, build_config)
+                } else if build_config.gui {
+                    // UI run loop; doesn't exit.
+                    gui::run(build_config)
+                } else {
+                    headless_run(output, build_config)
+                }
+            })
+        }
+
+        Command::Render(render_config) => {
+            emit_config(output, "render", &render_config);
+            sediment::Render::new(render_config).and_then(|render| render.with_output(output).run())
+        }
+
+        Command::Simplify(simplify_config) => {
+            emit_config(output, "simplify", &simplify_config);
+            sediment::simplifier::Simplifier::new(simplify_config)
+                .and_then(|simplifier| simplifier.with_output(output).run())
+        }
 
-fn main() {
-    let config = Config::parse();
+        Command::Batch(mut batch_config) => {
+            let flags = matches.subcommand_matches("batch").unwrap();
+            let options = resolve_options(
+                batch_config.preset,
+                batch_config.config.as_deref(),
+                &batch_config.options,
+                flags,
+            );
+            options.and_then(|options| {
+                batch_config.options = options;
+                emit_config(output, "batch", &batch_config);
+                Batch::new(batch_config).with_output(output).run()
+            })
+        }
+
+        Command::Inspect(inspect_config) => {
+            emit_config(output, "inspect", &inspect_config);
+            Inspector::new(inspect_config).and_then(|inspector| inspector.with_output(output).run())
+        }
 
-    match config.command {
-        Command::Build(build_config) => {
-            print_build_config(&build_config);
+        Command::Compare(compare_config) => {
+            emit_config(output, "compare", &compare_config);
+            Comparer::new(compare_config).with_output(output).run()
+        }
 
-            if build_config.gui {
-                // UI run loop; doesn't exit.
-                gui::run(build_config);
+        Command::Tune(mut tune_config) => {
+            let flags = matches.subcommand_matches("tune").unwrap();
+            let options = resolve_options(
+                tune_config.preset,
+                tune_config.config.as_deref(),
+                &tune_config.options,
+                flags,
+            );
+            options.and_then(|options| {
+                tune_config.options = options;
+                emit_config(output, "tune", &tune_config);
+                Tuner::new(tune_config).with_output(output).run()
+            })
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if output.is_json() {
+                output.emit("error", json!({ "message": e.to_string() }));
             } else {
-                headless_run(build_config);
+                eprintln!("Error: {}", e);
             }
+            ExitCode::FAILURE
         }
+    }
+}
 
-        Command::Render(render_config) => {
-            crate::render::Render::new(render_config).run();
-        }
+/// Layers the build options: the preset (or defaults), then the config file,
+/// then whichever flags were actually given on the command line
+fn resolve_options(
+    preset: Option<Preset>,
+    file: Option<&str>,
+    flags: &BuildOptions,
+    matches: &ArgMatches,
+) -> Result<BuildOptions> {
+    let mut options = preset.map(Preset::options).unwrap_or_default();
+
+    if let Some(path) = file {
+        options = options.with_file(path)?;
     }
+
+    let given: Vec<String> = matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .map(|id| id.as_str().replace('_', "-"))
+        .collect();
+    let options = options.with_overrides(flags, &given);
+
+    options.validate()?;
+    Ok(options)
 }
 
-fn headless_run(config: BuildConfig) {
-    let (builder_tx, builder_update_rx) = channel();
+fn headless_run(output: Output, config: BuildConfig) -> Result<()> {
+    let reference = Canvas::open(&config.input)?;
+    let log = progress_log(&config, &reference)?;
 
-    thread::spawn(move || {
-        let mut builder = Builder::new(builder_tx, config);
-        builder.run();
-    });
+    // stats as lines for people, or JSON without a line per shape
+    let json = output.is_json();
+    let printer = (!json).then_some(Printer);
+    let events = json.then(|| JsonLines::new("stdout", io::stdout()).without_shapes());
+
+    let mut builder = Builder::new(reference, config.options.clone())?
+        .with_observer(((printer, events), log))
+        .with_progress(update_interval(&config)?);
+    let shapes = builder.run()?;
+
+    write_outputs(output, &config, builder.image(), &shapes)?;
+
+    if json {
+        let outputs: Vec<&String> = [
+            &config.output,
+            &config.raw,
+            &config.bom,
+            &config.progress_log,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        output.emit(
+            "result",
+            json!({
+                "shapes": shapes.circles.len(),
+                "psnr": metrics::psnr(builder.reference(), builder.image()),
+                "ssim": metrics::ssim(builder.reference(), builder.image()),
+                "stats": stats_json(&builder.stats()),
+                "outputs": outputs,
+            }),
+        );
+    }
+    Ok(())
+}
 
-    loop {
-        match builder_update_rx.recv() {
-            Ok(BuilderUpdate::Stats(stats)) => print_stats(&stats),
-            Ok(_) => continue,
-            Err(_) => return,
+/// The progress log, if one was asked for
+fn progress_log(
+    config: &BuildConfig,
+    reference: &Canvas,
+) -> Result<Option<ProgressLog<BufWriter<File>>>> {
+    let Some(path) = &config.progress_log else {
+        return Ok(None);
+    };
+
+    ProgressLog::create(path, reference, progress_interval(config)?).map(Some)
+}
+
+/// Time between progress log rows
+fn progress_interval(config: &BuildConfig) -> Result<Duration> {
+    let seconds = config.progress_interval;
+    let invalid = || {
+        Error::Config(format!(
+            "progress-interval ({}) must be a positive number of seconds",
+            seconds
+        ))
+    };
+
+    if seconds.is_nan() || seconds <= 0.0 {
+        return Err(invalid());
+    }
+    Duration::try_from_secs_f32(seconds).map_err(|_| invalid())
+}
+
+/// How often the builder reports progress: ten times a second for the
+/// terminal or the GUI, or more often if the progress log wants it
+fn update_interval(config: &BuildConfig) -> Result<Duration> {
+    let updates = Duration::from_millis(100);
+    match config.progress_log {
+        Some(_) => Ok(progress_interval(config)?.min(updates)),
+        None => Ok(updates),
+    }
+}
+
+/// Writes out whichever results were asked for
+fn write_outputs(
+    output: Output,
+    config: &BuildConfig,
+    image: &Canvas,
+    shapes: &ShapeList,
+) -> Result<()> {
+    if let Some(path) = &config.output {
+        image.save(path)?;
+    }
+
+    if let Some(path) = &config.raw {
+        shapes.write(path)?;
+    }
+
+    if config.options.mode == BuildMode::Grid {
+        let parts = mosaic::bill_of_materials(&shapes.circles, shapes.palette.as_ref());
+        if output.is_json() {
+            output.emit("parts", json!({ "parts": parts }));
+        } else {
+            for part in &parts {
+                eprintln!("{:>8}  {}  {}", part.count, part.color, part.name);
+            }
+        }
+        if let Some(path) = &config.bom {
+            mosaic::write_bill_of_materials(&parts, path)?;
         }
     }
+
+    Ok(())
 }
 
-fn print_build_config(config: &BuildConfig) {
-    println!("{:#?}", config);
+fn print_build_config(output: Output, config: &BuildConfig) {
+    if output.is_json() {
+        emit_config(output, "build", config);
+    } else {
+        println!("{:#?}", config);
+    }
 }
 
-fn print_stats(stats: &Stats) {
-    println!(
-        "{}/{} {}% - {}s - Radius: {} ({}/{} {}%)",
-        stats.total_successes,
-        stats.total_attempts,
-        (100.0 * ((stats.total_successes as f32) / (stats.total_attempts as f32))) as u32,
-        stats.elapsed.as_secs(),
-        stats.radius,
-        stats.radius_successes,
-        stats.radius_attempts,
-        (100.0 * ((stats.radius_successes as f32) / (stats.radius_attempts as f32))) as u32,
+/// In JSON mode, reports the settings a command is about to run with
+fn emit_config(output: Output, command: &str, config: &impl Serialize) {
+    output.emit(
+        "config",
+        json!({ "command": command, "config": output::value(config) }),
     );
 }
@@ -0,0 +1,140 @@
+use crate::Canvas;
+use image::GenericImageView;
+
+/// Side of the square windows SSIM is computed over, and the step between them
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STEP: u32 = 4;
+
+/// PSNR of identical images, in decibels. Their true PSNR is infinite, which
+/// JSON can't hold; images a single level apart at one pixel score below this
+/// unless they have over 10^14 pixels, so nothing that differs reaches it.
+pub const MAX_PSNR: f64 = 200.0;
+
+/// Peak signal-to-noise ratio over the red, green and blue channels, in
+/// decibels; higher is closer, and identical images score `MAX_PSNR`.
+/// Images are compared over the area they share.
+pub fn psnr(a: &Canvas, b: &Canvas) -> f64 {
+    let width = a.width().min(b.width());
+    let height = a.height().min(b.height());
+
+    let mut squared_error = 0.0;
+    for y in 0..height {
+        for x in 0..width {
+            let pa = a.img.get_pixel(x, y).0;
+            let pb = b.img.get_pixel(x, y).0;
+            for c in 0..3 {
+                let d = pa[c] as f64 - pb[c] as f64;
+                squared_error += d * d;
+            }
+        }
+    }
+
+    if squared_error == 0.0 {
+        return MAX_PSNR;
+    }
+
+    let mse = squared_error / (width as f64 * height as f64 * 3.0).max(1.0);
+    (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR)
+}
+
+/// PSNR as it reads in a report: decibels, or "identical" at `MAX_PSNR`
+pub fn psnr_text(psnr: f64) -> String {
+    if psnr >= MAX_PSNR {
+        "identical".to_owned()
+    } else {
+        format!("{:.2} dB", psnr)
+    }
+}
+
+/// Mean structural similarity of the images' luminance, over overlapping
+/// windows: 1 for identical images, falling towards 0 (or below) as their
+/// structure diverges. Like PSNR, only the shared area is compared.
+pub fn ssim(a: &Canvas, b: &Canvas) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let width = a.width().min(b.width());
+    let height = a.height().min(b.height());
+    let la = luminance(a, width, height);
+    let lb = luminance(b, width, height);
+
+    // windows no bigger than the image, so small images still get one
+    let window = SSIM_WINDOW.min(width).min(height);
+    if window == 0 {
+        return 1.0;
+    }
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    let mut y = 0;
+    while y + window <= height {
+        let mut x = 0;
+        while x + window <= width {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for wy in y..y + window {
+                for wx in x..x + window {
+                    let i = (wy * width + wx) as usize;
+                    let (va, vb) = (la[i], lb[i]);
+                    sa += va;
+                    sb += vb;
+                    saa += va * va;
+                    sbb += vb * vb;
+                    sab += va * vb;
+                }
+            }
+
+            let n = (window * window) as f64;
+            let (ma, mb) = (sa / n, sb / n);
+            let var_a = saa / n - ma * ma;
+            let var_b = sbb / n - mb * mb;
+            let covariance = sab / n - ma * mb;
+
+            total += ((2.0 * ma * mb + C1) * (2.0 * covariance + C2))
+                / ((ma * ma + mb * mb + C1) * (var_a + var_b + C2));
+            windows += 1;
+            x += SSIM_STEP;
+        }
+        y += SSIM_STEP;
+    }
+
+    total / windows as f64
+}
+
+// luminance of each pixel in the given area, in row order
+fn luminance(canvas: &Canvas, width: u32, height: u32) -> Vec<f64> {
+    let mut values = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            values.push(Canvas::pixel_luminance(canvas.img.get_pixel(x, y)) as f64);
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output, Circle};
+    use image::Rgba;
+
+    #[test]
+    fn identical_images_match_perfectly() {
+        let mut a = Canvas::new(40, 30);
+        a.draw_circle(&Circle::new(20, 15, 10, Rgba([200, 100, 50, 255])));
+
+        assert_eq!(psnr(&a, &a), MAX_PSNR);
+        assert_eq!(output::value(&psnr(&a, &a)), serde_json::json!(MAX_PSNR));
+        assert_eq!(psnr_text(psnr(&a, &a)), "identical");
+        assert!((ssim(&a, &a) - 1.0).abs() < 1e-9);
+
+        let mut b = a.clone();
+        b.draw_circle(&Circle::new(10, 10, 5, Rgba([255, 255, 255, 255])));
+        let mut c = b.clone();
+        c.draw_circle(&Circle::new(30, 20, 8, Rgba([0, 255, 0, 255])));
+
+        // more damage, less similar
+        assert!(psnr(&a, &b) > psnr(&a, &c));
+        assert!(ssim(&a, &b) > ssim(&a, &c));
+        assert!(ssim(&a, &c) < 1.0);
+    }
+}
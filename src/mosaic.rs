@@ -0,0 +1,217 @@
+use crate::{palette::Palette, Canvas, Circle, Error, Lattice, Region, Result};
+use image::Rgba;
+use serde::Serialize;
+
+/// Centers of the mosaic's cells, row by row. Square lattices line cells up
+/// in columns; hex lattices shift every other row by half a cell and pack the
+/// rows closer together. Only whole cells that fit in the image are placed.
+pub fn cells(width: u32, height: u32, pitch: u32, lattice: Lattice) -> Vec<Vec<(u32, u32)>> {
+    let pitch = pitch.max(1) as f32;
+    let half = pitch / 2.0;
+    let row_spacing = match lattice {
+        Lattice::Square => pitch,
+        Lattice::Hex => pitch * 3f32.sqrt() / 2.0,
+    };
+
+    let mut rows = vec![];
+    let mut y = half;
+    while y + half <= height as f32 {
+        let offset = match lattice {
+            Lattice::Hex if rows.len() % 2 == 1 => half,
+            _ => 0.0,
+        };
+
+        let mut row = vec![];
+        let mut x = half + offset;
+        while x + half <= width as f32 {
+            row.push((x as u32, y as u32));
+            x += pitch;
+        }
+
+        rows.push(row);
+        y += row_spacing;
+    }
+
+    rows
+}
+
+/// Where a cell's leftover error goes when dithering: (rows down, columns
+/// across, share). Square lattices use Floyd-Steinberg; on a hex lattice a
+/// cell has one neighbour ahead in its row and two below it.
+fn diffusion(lattice: Lattice, row: usize) -> &'static [(usize, isize, f32)] {
+    match lattice {
+        Lattice::Square => &[
+            (0, 1, 7.0 / 16.0),
+            (1, -1, 3.0 / 16.0),
+            (1, 0, 5.0 / 16.0),
+            (1, 1, 1.0 / 16.0),
+        ],
+        // the row below is shifted right of an even row, left of an odd one
+        Lattice::Hex if row.is_multiple_of(2) => &[(0, 1, 0.5), (1, -1, 0.25), (1, 0, 0.25)],
+        Lattice::Hex => &[(0, 1, 0.5), (1, 0, 0.25), (1, 1, 0.25)],
+    }
+}
+
+/// Lays equal circles on the lattice, each the mean color of the reference
+/// under it. With a palette, colors snap to the nearest entry, and dithering
+/// carries each cell's error on to the cells not yet placed.
+pub fn build(
+    reference: &Canvas,
+    pitch: u32,
+    lattice: Lattice,
+    palette: Option<&Palette>,
+    dither: bool,
+) -> Vec<Circle> {
+    let rows = cells(reference.width(), reference.height(), pitch, lattice);
+    let radius = (pitch / 2).max(1);
+
+    let mut errors: Vec<Vec<[f32; 3]>> = rows.iter().map(|r| vec![[0.0; 3]; r.len()]).collect();
+    let mut circles = vec![];
+
+    for (row_index, row) in rows.iter().enumerate() {
+        for (column, (x, y)) in row.iter().enumerate() {
+            let mean = reference.section(&Region::new(*x, *y, radius)).mean_color();
+
+            let Some(palette) = palette else {
+                circles.push(Circle::new(*x, *y, radius, mean));
+                continue;
+            };
+
+            let error = errors[row_index][column];
+            let wanted = [0, 1, 2].map(|c| (mean[c] as f32 + error[c]).clamp(0.0, 255.0));
+            let color = palette.nearest(Rgba([
+                wanted[0].round() as u8,
+                wanted[1].round() as u8,
+                wanted[2].round() as u8,
+                255,
+            ]));
+            circles.push(Circle::new(*x, *y, radius, color));
+
+            if !dither {
+                continue;
+            }
+
+            let leftover = [0, 1, 2].map(|c| wanted[c] - color[c] as f32);
+            for (down, across, share) in diffusion(lattice, row_index) {
+                let target_row = row_index + down;
+                let Some(target_column) = column.checked_add_signed(*across) else {
+                    continue;
+                };
+                if let Some(cell) = errors
+                    .get_mut(target_row)
+                    .and_then(|r| r.get_mut(target_column))
+                {
+                    for c in 0..3 {
+                        cell[c] += leftover[c] * share;
+                    }
+                }
+            }
+        }
+    }
+
+    circles
+}
+
+/// One line of a bill of materials: how many pieces of a color to buy
+#[derive(Debug, Serialize)]
+pub struct Part {
+    pub color: String,
+    pub name: String,
+    pub count: usize,
+}
+
+/// Counts the pieces of each color. With a palette, parts are listed in
+/// palette order with the palette's names, skipping colors that aren't used;
+/// otherwise they're listed most used first.
+pub fn bill_of_materials(circles: &[Circle], palette: Option<&Palette>) -> Vec<Part> {
+    let mut counts: Vec<(Rgba<u8>, usize)> = vec![];
+    for c in circles {
+        let color = Rgba([c.r, c.g, c.b, 255]);
+        match counts.iter_mut().find(|(k, _)| *k == color) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((color, 1)),
+        }
+    }
+
+    let count_of = |color: Rgba<u8>| {
+        counts
+            .iter()
+            .find(|(k, _)| *k == color)
+            .map(|(_, count)| *count)
+            .unwrap_or_default()
+    };
+
+    match palette {
+        Some(palette) => palette
+            .entries
+            .iter()
+            .map(|e| Part {
+                color: Palette::hex(e.color),
+                name: e.name.clone(),
+                count: count_of(e.color),
+            })
+            .filter(|p| p.count > 0)
+            .collect(),
+        None => {
+            counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+            counts
+                .into_iter()
+                .map(|(color, count)| Part {
+                    color: Palette::hex(color),
+                    name: String::new(),
+                    count,
+                })
+                .collect()
+        }
+    }
+}
+
+pub fn write_bill_of_materials(parts: &[Part], path: &str) -> Result<()> {
+    let io_error = |e: csv::Error| Error::io(path, e.into());
+    let mut writer = csv::Writer::from_path(path).map_err(io_error)?;
+    for part in parts {
+        writer.serialize(part).map_err(io_error)?;
+    }
+    writer.flush().map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    #[test]
+    fn lays_out_lattices() {
+        let square = cells(40, 30, 10, Lattice::Square);
+        assert_eq!(square.len(), 3);
+        assert!(square.iter().all(|r| r.len() == 4));
+        assert_eq!(square[1][2], (25, 15));
+
+        // offset rows lose the cell that would hang off the right edge
+        let hex = cells(40, 30, 10, Lattice::Hex);
+        assert_eq!(hex.len(), 3);
+        assert_eq!(hex[0].len(), 4);
+        assert_eq!(hex[1].len(), 3);
+        assert_eq!(hex[1][0], (10, 13));
+    }
+
+    #[test]
+    fn dithers_mid_tones() {
+        let gray = Canvas::filled(80, 80, Rgba([128, 128, 128, 255]));
+        let palette = Palette::new(vec![BLACK, WHITE]);
+
+        // without dithering every cell rounds the same way
+        let flat = build(&gray, 8, Lattice::Square, Some(&palette), false);
+        let parts = bill_of_materials(&flat, Some(&palette));
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].count, 100);
+
+        // with it, the grays come out as an even mix
+        let dithered = build(&gray, 8, Lattice::Square, Some(&palette), true);
+        let parts = bill_of_materials(&dithered, Some(&palette));
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|p| p.count.abs_diff(50) <= 5));
+    }
+}
@@ -0,0 +1,506 @@
+use crate::{builder::Stats, metrics, output, palette::Palette, Canvas, Circle, Error, Result};
+use image::Rgba;
+use serde::Serialize;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Hears about a build as it goes. Every method does nothing by default, so
+/// an observer only implements what it cares about; returning an error stops
+/// the build with that error.
+pub trait Observer: Send {
+    /// A shape was placed on the canvas
+    fn on_shape_committed(&mut self, _circle: &Circle, _stats: &Stats) -> Result<()> {
+        Ok(())
+    }
+
+    /// The search gave up on one radius and moved down to the next. `stats`
+    /// holds the attempts and success rate at the old radius, and the new one.
+    fn on_radius_changed(&mut self, _stats: &Stats, _image: &Canvas) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called every so often, if the builder was asked to with
+    /// `Builder::with_progress`. The image is only borrowed; observers that
+    /// want a snapshot of it have to clone it themselves.
+    fn on_progress(&mut self, _stats: &Stats, _image: &Canvas) -> Result<()> {
+        Ok(())
+    }
+
+    /// The build is done; `image` is the finished canvas
+    fn on_finished(&mut self, _stats: &Stats, _image: &Canvas) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Ignores everything; what a builder reports to until told otherwise
+pub struct NoOp;
+
+impl Observer for NoOp {}
+
+/// A pair of observers hears everything, the first one first
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn on_shape_committed(&mut self, circle: &Circle, stats: &Stats) -> Result<()> {
+        self.0.on_shape_committed(circle, stats)?;
+        self.1.on_shape_committed(circle, stats)
+    }
+
+    fn on_radius_changed(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.0.on_radius_changed(stats, image)?;
+        self.1.on_radius_changed(stats, image)
+    }
+
+    fn on_progress(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.0.on_progress(stats, image)?;
+        self.1.on_progress(stats, image)
+    }
+
+    fn on_finished(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.0.on_finished(stats, image)?;
+        self.1.on_finished(stats, image)
+    }
+}
+
+/// An observer that may not be there, for ones turned on by a flag
+impl<O: Observer> Observer for Option<O> {
+    fn on_shape_committed(&mut self, circle: &Circle, stats: &Stats) -> Result<()> {
+        self.as_mut()
+            .map_or(Ok(()), |o| o.on_shape_committed(circle, stats))
+    }
+
+    fn on_radius_changed(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.as_mut()
+            .map_or(Ok(()), |o| o.on_radius_changed(stats, image))
+    }
+
+    fn on_progress(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.as_mut()
+            .map_or(Ok(()), |o| o.on_progress(stats, image))
+    }
+
+    fn on_finished(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.as_mut()
+            .map_or(Ok(()), |o| o.on_finished(stats, image))
+    }
+}
+
+pub enum BuilderUpdate {
+    Preview(image::DynamicImage),
+    Stats(Stats),
+}
+
+/// Forwards progress to another thread, such as a UI. Stats are always sent;
+/// copies of the image only if previews are turned on.
+pub struct Channel {
+    tx: Sender<BuilderUpdate>,
+    previews: bool,
+}
+
+impl Channel {
+    pub fn new(tx: Sender<BuilderUpdate>) -> Self {
+        Self {
+            tx,
+            previews: false,
+        }
+    }
+
+    /// Sends a copy of the image along with each update
+    pub fn with_previews(mut self) -> Self {
+        self.previews = true;
+        self
+    }
+
+    fn send(&self, stats: &Stats, image: &Canvas) -> Result<()> {
+        if self.previews {
+            self.tx
+                .send(BuilderUpdate::Preview(image.img.clone()))
+                .map_err(|_| Error::ChannelClosed)?;
+        }
+        self.tx
+            .send(BuilderUpdate::Stats(*stats))
+            .map_err(|_| Error::ChannelClosed)
+    }
+}
+
+impl Observer for Channel {
+    fn on_progress(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.send(stats, image)
+    }
+
+    fn on_finished(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.send(stats, image)
+    }
+}
+
+/// Prints a line of stats with each update, and notes radius changes on
+/// stderr; what a headless build shows on the terminal
+pub struct Printer;
+
+impl Printer {
+    fn print_stats(stats: &Stats) {
+        println!(
+            "{}/{} {}% - {}s - Radius: {} ({}/{} {}%)",
+            stats.total_successes,
+            stats.total_attempts,
+            (100.0 * ((stats.total_successes as f32) / (stats.total_attempts as f32))) as u32,
+            stats.elapsed.as_secs(),
+            stats.radius,
+            stats.radius_successes,
+            stats.radius_attempts,
+            (100.0 * ((stats.radius_successes as f32) / (stats.radius_attempts as f32))) as u32,
+        );
+    }
+}
+
+impl Observer for Printer {
+    fn on_radius_changed(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+        eprintln!(
+            "Success rate: {} ({} attempts) ... new radius: {}",
+            stats.radius_success_rate, stats.radius_attempts, stats.radius
+        );
+        Ok(())
+    }
+
+    fn on_progress(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+        Self::print_stats(stats);
+        Ok(())
+    }
+
+    fn on_finished(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+        Self::print_stats(stats);
+        Ok(())
+    }
+}
+
+/// Writes every event as a line of JSON, for other programs to follow along.
+/// `name` says where the lines go, for error messages.
+pub struct JsonLines<W> {
+    name: String,
+    writer: W,
+    shapes: bool,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(name: &str, writer: W) -> Self {
+        Self {
+            name: name.to_owned(),
+            writer,
+            shapes: true,
+        }
+    }
+
+    /// Leaves out the line per shape, for following along with just the stats
+    pub fn without_shapes(mut self) -> Self {
+        self.shapes = false;
+        self
+    }
+
+    fn write(&mut self, event: serde_json::Value) -> Result<()> {
+        writeln!(self.writer, "{}", event)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| Error::io(&self.name, e))
+    }
+}
+
+/// Stats as a JSON object, with the elapsed time in seconds
+pub fn stats_json(stats: &Stats) -> serde_json::Value {
+    json!({
+        "total_attempts": stats.total_attempts,
+        "total_successes": stats.total_successes,
+        "total_skips": stats.total_skips,
+        "radius_attempts": stats.radius_attempts,
+        "radius_successes": stats.radius_successes,
+        "radius_success_rate": output::value(&stats.radius_success_rate),
+        "radius": stats.radius,
+        "delta": stats.delta,
+        "elapsed": stats.elapsed.as_secs_f64(),
+    })
+}
+
+impl<W: Write + Send> Observer for JsonLines<W> {
+    fn on_shape_committed(&mut self, circle: &Circle, _stats: &Stats) -> Result<()> {
+        if !self.shapes {
+            return Ok(());
+        }
+        self.write(json!({
+            "event": "shape",
+            "x": circle.x,
+            "y": circle.y,
+            "radius": circle.radius,
+            "color": Palette::hex(Rgba([circle.r, circle.g, circle.b, 255])),
+        }))
+    }
+
+    fn on_radius_changed(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+        self.write(json!({ "event": "radius", "stats": stats_json(stats) }))
+    }
+
+    fn on_progress(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+        self.write(json!({ "event": "progress", "stats": stats_json(stats) }))
+    }
+
+    fn on_finished(&mut self, stats: &Stats, _image: &Canvas) -> Result<()> {
+        self.write(json!({ "event": "finished", "stats": stats_json(stats) }))
+    }
+}
+
+/// How a progress log is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A header row, then a row per snapshot
+    Csv,
+    /// A JSON object per line
+    JsonLines,
+}
+
+impl LogFormat {
+    /// .csv files get CSV; .jsonl, .ndjson and .json files get JSON lines
+    pub fn for_path(path: &str) -> Result<Self> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("csv") => Ok(Self::Csv),
+            Some("jsonl" | "ndjson" | "json") => Ok(Self::JsonLines),
+            _ => Err(Error::Config(format!(
+                "{}: progress logs must be .csv or .jsonl",
+                path
+            ))),
+        }
+    }
+}
+
+/// One row of a progress log
+#[derive(Serialize, Debug)]
+struct Snapshot {
+    // seconds since the Unix epoch
+    timestamp: f64,
+    // "radius", "progress" or "finished"
+    event: &'static str,
+    elapsed: f64,
+    total_attempts: usize,
+    total_successes: usize,
+    total_skips: usize,
+    radius: u32,
+    radius_attempts: usize,
+    radius_successes: usize,
+    radius_success_rate: f32,
+    delta: usize,
+    psnr: f64,
+}
+
+enum Sink<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    JsonLines(W),
+}
+
+/// Records the build's stats over time, for charting how it converges: a
+/// snapshot at every radius change, at most one per `interval` in between,
+/// and one at the end. Progress snapshots need `Builder::with_progress` at an
+/// interval no longer than the log's.
+pub struct ProgressLog<W: Write> {
+    name: String,
+    sink: Sink<W>,
+    // what the PSNR is measured against
+    reference: Canvas,
+    interval: Duration,
+    last_snapshot: Option<Instant>,
+}
+
+impl ProgressLog<BufWriter<File>> {
+    /// Logs to a file, in the format its extension calls for
+    pub fn create(path: &str, reference: &Canvas, interval: Duration) -> Result<Self> {
+        let format = LogFormat::for_path(path)?;
+        let file = File::create(path).map_err(|e| Error::io(path, e))?;
+        Ok(Self::new(
+            path,
+            BufWriter::new(file),
+            format,
+            reference,
+            interval,
+        ))
+    }
+}
+
+impl<W: Write + Send> ProgressLog<W> {
+    pub fn new(
+        name: &str,
+        writer: W,
+        format: LogFormat,
+        reference: &Canvas,
+        interval: Duration,
+    ) -> Self {
+        let sink = match format {
+            LogFormat::Csv => Sink::Csv(Box::new(csv::Writer::from_writer(writer))),
+            LogFormat::JsonLines => Sink::JsonLines(writer),
+        };
+
+        Self {
+            name: name.to_owned(),
+            sink,
+            reference: reference.clone(),
+            interval,
+            last_snapshot: None,
+        }
+    }
+
+    fn record(&mut self, event: &'static str, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.last_snapshot = Some(Instant::now());
+        let snapshot = Snapshot {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            event,
+            elapsed: stats.elapsed.as_secs_f64(),
+            total_attempts: stats.total_attempts,
+            total_successes: stats.total_successes,
+            total_skips: stats.total_skips,
+            radius: stats.radius,
+            radius_attempts: stats.radius_attempts,
+            radius_successes: stats.radius_successes,
+            radius_success_rate: stats.radius_success_rate,
+            delta: stats.delta,
+            psnr: metrics::psnr(&self.reference, image),
+        };
+
+        // flushed as we go, so the log is useful while the build runs, or if
+        // it never finishes
+        let name = &self.name;
+        match &mut self.sink {
+            Sink::Csv(writer) => writer
+                .serialize(snapshot)
+                .map_err(|e| Error::io(name, e.into()))
+                .and_then(|_| writer.flush().map_err(|e| Error::io(name, e))),
+            Sink::JsonLines(writer) => {
+                writeln!(writer, "{}", serde_json::to_string(&snapshot).unwrap())
+                    .and_then(|_| writer.flush())
+                    .map_err(|e| Error::io(name, e))
+            }
+        }
+    }
+}
+
+impl<W: Write + Send> Observer for ProgressLog<W> {
+    fn on_radius_changed(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.record("radius", stats, image)
+    }
+
+    fn on_progress(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        if self
+            .last_snapshot
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+        self.record("progress", stats, image)
+    }
+
+    fn on_finished(&mut self, stats: &Stats, image: &Canvas) -> Result<()> {
+        self.record("finished", stats, image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildOptions, Builder};
+    use std::sync::mpsc::channel;
+
+    // passes along each shape it hears about, and None when the build ends
+    struct Recorder(Sender<Option<Circle>>);
+
+    impl Observer for Recorder {
+        fn on_shape_committed(&mut self, circle: &Circle, _stats: &Stats) -> Result<()> {
+            self.0.send(Some(*circle)).map_err(|_| Error::ChannelClosed)
+        }
+
+        fn on_finished(&mut self, _stats: &Stats, _image: &Canvas) -> Result<()> {
+            self.0.send(None).map_err(|_| Error::ChannelClosed)
+        }
+    }
+
+    #[test]
+    fn hears_about_every_shape() {
+        let mut canvas = Canvas::new(64, 48);
+        canvas.draw_circle(&Circle::new(32, 24, 16, Rgba([255, 255, 255, 255])));
+        let options = BuildOptions {
+            max_radius: 20,
+            min_radius: 4,
+            ..Default::default()
+        };
+
+        let (tx, rx) = channel();
+        let shapes = Builder::new(canvas, options)
+            .unwrap()
+            .with_observer(Recorder(tx))
+            .run()
+            .unwrap();
+
+        let mut events: Vec<Option<Circle>> = rx.iter().collect();
+        assert_eq!(events.pop(), Some(None));
+        let heard: Vec<Circle> = events.into_iter().flatten().collect();
+        assert_eq!(heard, shapes.circles);
+    }
+
+    #[test]
+    fn writes_a_line_per_event() {
+        let stats = Stats {
+            radius: 12,
+            ..Default::default()
+        };
+        let mut log = JsonLines::new("log", vec![]);
+        log.on_shape_committed(&Circle::new(1, 2, 3, Rgba([255, 0, 0, 255])), &stats)
+            .unwrap();
+        log.on_radius_changed(&stats, &Canvas::new(4, 4)).unwrap();
+
+        let text = String::from_utf8(log.writer).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "shape");
+        assert_eq!(lines[0]["color"], "#ff0000");
+        assert_eq!(lines[1]["stats"]["radius"], 12);
+    }
+
+    #[test]
+    fn logs_snapshots_as_csv() {
+        let reference = Canvas::new(8, 8);
+        let stats = Stats {
+            radius: 5,
+            total_attempts: 40,
+            ..Default::default()
+        };
+        let mut log = ProgressLog::new(
+            "log",
+            vec![],
+            LogFormat::Csv,
+            &reference,
+            Duration::from_secs(60),
+        );
+        log.on_radius_changed(&stats, &reference).unwrap();
+        // too soon after the last snapshot
+        log.on_progress(&stats, &reference).unwrap();
+        log.on_finished(&stats, &reference).unwrap();
+
+        let Sink::Csv(writer) = log.sink else {
+            unreachable!();
+        };
+        let text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let mut rows = csv::Reader::from_reader(text.as_bytes());
+        let headers = rows.headers().unwrap().clone();
+        let rows: Vec<csv::StringRecord> = rows.records().map(|r| r.unwrap()).collect();
+
+        assert_eq!(&headers[1], "event");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][1], "radius");
+        assert_eq!(&rows[1][1], "finished");
+        assert_eq!(&rows[1][3], "40");
+        // identical images
+        assert_eq!(&rows[1][11], "200.0");
+    }
+}
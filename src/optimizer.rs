@@ -1,43 +1,104 @@
-use crate::{Canvas, Circle, Region, Render};
+use crate::{
+    id_buffer::IdBuffer, output::Output, spatial_index::SpatialIndex, Canvas, Circle, Error,
+    Region, Render, Result,
+};
 use rayon::prelude::*;
 use std::{
-    fmt::Write,
+    cmp::Reverse,
+    collections::BinaryHeap,
     sync::mpsc::{channel, Sender},
     thread,
     time::Instant,
 };
 
+use image::Rgba;
+#[cfg(feature = "cli")]
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 
+/// Stands in for indicatif's bar when there's no command line to draw it on
+#[cfg(not(feature = "cli"))]
+struct ProgressBar;
+
+#[cfg(not(feature = "cli"))]
+impl ProgressBar {
+    fn set_position(&self, _: u64) {}
+    fn inc(&self, _: u64) {}
+    fn finish(&self) {}
+    fn finish_with_message(&self, _: &'static str) {}
+}
+
 pub struct Optimizer {
     circles: Vec<Circle>,
     reference: Canvas,
+    index: SpatialIndex,
+    // color the circles are drawn over
+    background: Rgba<u8>,
+    output: Output,
 }
 
 impl Optimizer {
-    pub fn new(circles: Vec<Circle>) -> Self {
-        let reference = Render::render_raster(&circles);
-        Self { circles, reference }
+    pub fn new(circles: Vec<Circle>, background: Rgba<u8>) -> Self {
+        let width = Render::image_width(&circles);
+        let height = Render::image_height(&circles);
+        let reference = Render::render_raster_on(&circles, width, height, background);
+        let index = SpatialIndex::from_circles(&circles);
+        Self {
+            circles,
+            reference,
+            index,
+            background,
+            output: Output::default(),
+        }
+    }
+
+    /// Builds an optimizer that measures fidelity against the given image,
+    /// rather than against the render of the circles themselves. Fails if the
+    /// image is too small to be the one the circles were built from.
+    pub fn with_reference(
+        circles: Vec<Circle>,
+        reference: Canvas,
+        background: Rgba<u8>,
+    ) -> Result<Self> {
+        // every build places circle centers inside the reference
+        let width = circles.iter().map(|c| c.x + 1).max().unwrap_or_default();
+        let height = circles.iter().map(|c| c.y + 1).max().unwrap_or_default();
+        if width > reference.width() || height > reference.height() {
+            return Err(Error::Config(format!(
+                "reference image is {}x{}, but the shapes were built from an image at least {}x{}",
+                reference.width(),
+                reference.height(),
+                width,
+                height
+            )));
+        }
+
+        let index = SpatialIndex::from_circles(&circles);
+        Ok(Self {
+            circles,
+            reference,
+            index,
+            background,
+            output: Output::default(),
+        })
+    }
+
+    /// Where progress goes; messages on stderr unless told otherwise
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
     }
 
     pub fn parallel_prune(&self) -> Vec<Circle> {
-        eprintln!("Pruning {} circles ...", self.circles.len());
+        self.output
+            .status(format!("Pruning {} circles ...", self.circles.len()));
 
         // start progress bar in it's own thread
         let (progress_tx, progress_rx) = channel();
         let target_count = self.circles.len();
+        let output = self.output;
         thread::spawn(move || {
             let mut count = 0;
-            let pb = ProgressBar::new(target_count as u64);
-            pb.set_style(
-                ProgressStyle::with_template(
-                    "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} (eta: {eta})",
-                )
-                .unwrap()
-                .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
-                    write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
-                })
-            );
+            let pb = Self::progress_bar(output, target_count);
             for _ in progress_rx.iter() {
                 count += 1;
                 pb.set_position(count as u64);
@@ -52,22 +113,343 @@ impl Optimizer {
         let pruned_circles: Vec<Circle> = self
             .circles
             .par_iter()
-            .filter(|c| Self::test_circle(&self.reference, &self.circles, **c, progress_tx.clone()))
+            .filter(|c| {
+                Self::test_circle(
+                    &self.reference,
+                    &self.circles,
+                    &self.index,
+                    self.background,
+                    **c,
+                    progress_tx.clone(),
+                )
+            })
             .cloned()
             .collect();
 
-        eprintln!(
+        self.output.status(format!(
             "Pruned to {} circles in {:?}",
             pruned_circles.len(),
             timer.elapsed()
+        ));
+
+        pruned_circles
+    }
+
+    #[cfg(not(feature = "cli"))]
+    fn progress_bar(_: Output, _: usize) -> ProgressBar {
+        ProgressBar
+    }
+
+    #[cfg(feature = "cli")]
+    fn progress_bar(output: Output, length: usize) -> ProgressBar {
+        use std::fmt::Write;
+
+        // a bar is for people; JSON mode reports the start and end instead
+        if output.is_json() {
+            return ProgressBar::hidden();
+        }
+
+        let pb = ProgressBar::new(length as u64);
+        pb.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} (eta: {eta})",
+            )
+            .unwrap()
+            .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+                write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
+            }),
         );
+        pb
+    }
+
+    /// Occlusion-aware pruning that never changes the rendered image.
+    ///
+    /// Circles entirely covered by a later circle are dropped first; this is a
+    /// purely geometric test and safe to run in parallel, since whichever circle
+    /// ends a chain of covers is kept and hides everything under it. The rest
+    /// are tested one at a time against the circles kept so far, so removals
+    /// can't combine to change a pixel the way independent tests can. Circles
+    /// that define the image bounds are always kept.
+    pub fn precise_prune(&self) -> Vec<Circle> {
+        self.output.status(format!(
+            "Precisely pruning {} circles ...",
+            self.circles.len()
+        ));
+        let timer = Instant::now();
+
+        let mut kept: Vec<bool> = (0..self.circles.len())
+            .into_par_iter()
+            .map(|i| !self.is_covered(i))
+            .collect();
+
+        let bounds = self.bounding_circles();
+        let pb = Self::progress_bar(self.output, self.circles.len());
+        for i in 0..self.circles.len() {
+            pb.inc(1);
+
+            let candidate = self.circles[i];
+            if !kept[i] || bounds[i] {
+                continue;
+            }
+
+            // compare one pixel past the circle's edge, and pull in anything
+            // whose rasterized edge could reach into the compared region
+            let region = Region::new(candidate.x, candidate.y, candidate.radius + 1);
+            let search = Region::new(candidate.x, candidate.y, candidate.radius + 2);
+            let nearby: Vec<usize> = self
+                .index
+                .overlapping_region(&self.circles, &search)
+                .into_iter()
+                .filter(|j| kept[*j])
+                .collect();
+
+            let with = self.render_region(&region, &nearby, None);
+            let without = self.render_region(&region, &nearby, Some(i));
+
+            if with.is_equal(&without) {
+                kept[i] = false;
+            }
+        }
+        pb.finish();
+
+        let pruned_circles: Vec<Circle> = self
+            .circles
+            .iter()
+            .zip(kept)
+            .filter(|(_, keep)| *keep)
+            .map(|(c, _)| *c)
+            .collect();
+
+        self.output.status(format!(
+            "Pruned to {} circles in {:?}",
+            pruned_circles.len(),
+            timer.elapsed()
+        ));
 
         pruned_circles
     }
 
+    /// Lossy simplification: greedily removes the circles whose removal adds
+    /// the least error against the reference, until at most `target` remain or
+    /// the next removal would push the mean per-channel error (0-255) past
+    /// `max_error`. With neither limit set, nothing is removed.
+    pub fn simplify(&self, target: Option<usize>, max_error: Option<f32>) -> Vec<Circle> {
+        if target.is_none() && max_error.is_none() {
+            return self.circles.clone();
+        }
+
+        self.output
+            .status(format!("Simplifying {} circles ...", self.circles.len()));
+        let timer = Instant::now();
+
+        let channels = (self.reference.width() as f32) * (self.reference.height() as f32) * 3.0;
+        let mut kept = vec![true; self.circles.len()];
+        let mut remaining = self.circles.len();
+
+        let mut total_error = self.error_of(&self.circles) as i64;
+
+        self.output.status(format!(
+            "Starting error: {:.3}",
+            (total_error as f32) / channels
+        ));
+
+        // circles that define the image bounds are never candidates
+        let bounds = self.bounding_circles();
+        let mut queue: BinaryHeap<Reverse<(i64, usize)>> = (0..self.circles.len())
+            .into_par_iter()
+            .filter(|i| !bounds[*i])
+            .map(|i| Reverse((self.removal_cost(i, &kept), i)))
+            .collect::<Vec<_>>()
+            .into();
+
+        while target.is_none_or(|t| remaining > t) {
+            let Some(Reverse((cost, i))) = queue.pop() else {
+                break;
+            };
+
+            // costs go stale as neighbours are removed, so re-check before
+            // acting; if it's no longer the cheapest, put it back in line
+            let cost = match self.removal_cost(i, &kept) {
+                fresh if fresh > cost => {
+                    if queue.peek().is_some_and(|Reverse((next, _))| fresh > *next) {
+                        queue.push(Reverse((fresh, i)));
+                        continue;
+                    }
+                    fresh
+                }
+                fresh => fresh,
+            };
+
+            if let Some(max_error) = max_error {
+                if ((total_error + cost) as f32) / channels > max_error {
+                    break;
+                }
+            }
+
+            kept[i] = false;
+            remaining -= 1;
+            total_error += cost;
+        }
+
+        let simplified: Vec<Circle> = self
+            .circles
+            .iter()
+            .zip(kept)
+            .filter(|(_, keep)| *keep)
+            .map(|(c, _)| *c)
+            .collect();
+
+        self.output.status(format!(
+            "Simplified to {} circles in {:?}, error: {:.3}",
+            simplified.len(),
+            timer.elapsed(),
+            (total_error as f32) / channels
+        ));
+
+        simplified
+    }
+
+    /// Recomputes every circle's color to best match the reference over the
+    /// pixels where that circle ends up on top, keeping geometry and order.
+    ///
+    /// The best color for a circle is the per-channel median of the reference
+    /// pixels it shows, which minimizes the absolute error used everywhere
+    /// else. Which pixels a circle shows depends only on geometry, never on
+    /// colors, so each circle's fit is independent of the others and a single
+    /// pass is already converged.
+    pub fn refit_colors(&self) -> Vec<Circle> {
+        let timer = Instant::now();
+        let width = self.reference.width();
+        let height = self.reference.height();
+        let channels = (width as f32) * (height as f32) * 3.0;
+
+        let ids = IdBuffer::render(&self.circles, width, height);
+        let groups = ids.pixels_by_circle(self.circles.len());
+        let reference = self.reference.img.as_bytes();
+
+        let refit: Vec<Circle> = self
+            .circles
+            .par_iter()
+            .zip(groups.par_iter())
+            .map(|(circle, pixels)| {
+                if pixels.is_empty() {
+                    return *circle;
+                }
+
+                let mut median = [0u8; 3];
+                let mut values = Vec::with_capacity(pixels.len());
+                for (channel, m) in median.iter_mut().enumerate() {
+                    values.clear();
+                    values.extend(pixels.iter().map(|p| reference[*p as usize * 4 + channel]));
+                    let middle = values.len() / 2;
+                    *m = *values.select_nth_unstable(middle).1;
+                }
+
+                Circle::new(
+                    circle.x,
+                    circle.y,
+                    circle.radius,
+                    Rgba([median[0], median[1], median[2], 255]),
+                )
+            })
+            .collect();
+
+        self.output.status(format!(
+            "Refit {} colors in {:?}, error: {:.3} -> {:.3}",
+            refit.len(),
+            timer.elapsed(),
+            (self.error_of(&self.circles) as f32) / channels,
+            (self.error_of(&refit) as f32) / channels,
+        ));
+
+        refit
+    }
+
+    // total delta between the reference and the circles rendered at its size
+    fn error_of(&self, circles: &[Circle]) -> usize {
+        let width = self.reference.width();
+        let height = self.reference.height();
+        self.reference
+            .delta(&Render::render_raster_on(circles, width, height, self.background).img)
+    }
+
+    // flags the circles whose edges set the rendered image's width or height;
+    // removing one of them would change the size of the output
+    fn bounding_circles(&self) -> Vec<bool> {
+        let width = Render::image_width(&self.circles);
+        let height = Render::image_height(&self.circles);
+
+        self.circles
+            .iter()
+            .map(|c| c.x + c.radius == width || c.y + c.radius == height)
+            .collect()
+    }
+
+    // how much the error against the reference grows if the circle at `index`
+    // is removed from the kept circles; negative if removing it helps
+    fn removal_cost(&self, index: usize, kept: &[bool]) -> i64 {
+        let candidate = &self.circles[index];
+        let region = Region::new(candidate.x, candidate.y, candidate.radius + 1);
+        let search = Region::new(candidate.x, candidate.y, candidate.radius + 2);
+        let nearby: Vec<usize> = self
+            .index
+            .overlapping_region(&self.circles, &search)
+            .into_iter()
+            .filter(|j| kept[*j])
+            .collect();
+
+        let reference = self.reference.section(&region);
+        let with = self.render_region(&region, &nearby, None);
+        let without = self.render_region(&region, &nearby, Some(index));
+
+        reference.delta(&without.img) as i64 - reference.delta(&with.img) as i64
+    }
+
+    // true if a later circle hides every pixel of the circle at `index`
+    fn is_covered(&self, index: usize) -> bool {
+        let candidate = &self.circles[index];
+        let region = Region::new(candidate.x, candidate.y, candidate.radius);
+
+        self.index
+            .overlapping_region(&self.circles, &region)
+            .into_iter()
+            .any(|j| j > index && self.circles[j].contains_circle(candidate))
+    }
+
+    // renders the listed circles (in order) onto a canvas covering only the
+    // region (clipped to the reference), optionally leaving one of them out
+    fn render_region(&self, region: &Region, indices: &[usize], skip: Option<usize>) -> Canvas {
+        let origin_x = region.real_origin_x();
+        let origin_y = region.real_origin_y();
+        let width = region
+            .real_width()
+            .min(self.reference.width().saturating_sub(origin_x));
+        let height = region
+            .real_height()
+            .min(self.reference.height().saturating_sub(origin_y));
+
+        let mut canvas = Canvas::filled(width, height, self.background);
+        let origin_x = origin_x as i32;
+        let origin_y = origin_y as i32;
+
+        for i in indices.iter().filter(|i| Some(**i) != skip) {
+            let c = &self.circles[*i];
+            imageproc::drawing::draw_filled_circle_mut(
+                &mut canvas.img,
+                (c.x as i32 - origin_x, c.y as i32 - origin_y),
+                c.radius as i32,
+                Rgba([c.r, c.g, c.b, 255]),
+            );
+        }
+
+        canvas
+    }
+
     pub fn test_circle(
         reference: &Canvas,
         circles: &[Circle],
+        index: &SpatialIndex,
+        background: Rgba<u8>,
         candidate: Circle,
         progress: Sender<usize>,
     ) -> bool {
@@ -75,13 +457,17 @@ impl Optimizer {
         let candidate_region = Region::new(candidate.x, candidate.y, candidate.radius);
 
         // find all of the circles that overlap our candidate region
-        let overlapping_circles: Vec<Circle> = circles
-            .iter()
-            .filter(|c| c.overlaps_region(&candidate_region))
-            .cloned()
+        let overlapping_circles: Vec<Circle> = index
+            .overlapping_region(circles, &candidate_region)
+            .into_iter()
+            .map(|i| circles[i])
             .collect();
 
-        let mut local_canvas = Render::create_empty_canvas(&overlapping_circles);
+        let mut local_canvas = Canvas::filled(
+            Render::image_width(&overlapping_circles),
+            Render::image_height(&overlapping_circles),
+            background,
+        );
         for c in overlapping_circles.iter() {
             if c != &candidate {
                 local_canvas.draw_circle(c);
@@ -95,9 +481,143 @@ impl Optimizer {
         // if the canvases are equal, then the candidate circle is redundant
         let result = !test_canvas.is_equal(&reference_canvas);
 
-        // update the counter
-        progress.send(1).unwrap();
+        // update the counter; the bar is only for show, so a closed channel
+        // doesn't stop the prune
+        let _ = progress.send(1);
 
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    fn random_circles(seed: u64, count: usize) -> Vec<Circle> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let palette = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [0, 0, 0]];
+
+        (0..count)
+            .map(|_| {
+                let [r, g, b] = palette[rng.gen_range(0..palette.len())];
+                Circle::new(
+                    rng.gen_range(0..160),
+                    rng.gen_range(0..120),
+                    rng.gen_range(1..40),
+                    Rgba([r, g, b, 255]),
+                )
+            })
+            .collect()
+    }
+
+    fn png_bytes(circles: &[Circle]) -> Vec<u8> {
+        let mut bytes = std::io::Cursor::new(vec![]);
+        Render::render_raster(circles)
+            .img
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn precise_prune_never_changes_the_png() {
+        for seed in 0..5 {
+            let circles = random_circles(seed, 200);
+            let pruned = Optimizer::new(circles.clone(), BLACK).precise_prune();
+
+            assert!(pruned.len() < circles.len());
+            assert_eq!(png_bytes(&pruned), png_bytes(&circles), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn simplifies_to_target() {
+        let circles = random_circles(1, 100);
+        let optimizer = Optimizer::new(circles.clone(), BLACK);
+
+        let simplified = optimizer.simplify(Some(40), None);
+        assert_eq!(simplified.len(), 40);
+
+        // survivors keep their original order
+        let positions: Vec<usize> = simplified
+            .iter()
+            .map(|c| circles.iter().position(|o| o == c).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn zero_error_budget_is_lossless() {
+        let circles = random_circles(2, 100);
+        let simplified = Optimizer::new(circles.clone(), BLACK).simplify(None, Some(0.0));
+
+        assert_eq!(png_bytes(&simplified), png_bytes(&circles));
+    }
+
+    #[test]
+    fn refit_never_increases_error() {
+        let circles = random_circles(3, 60);
+        let mut reference = Canvas::new(160, 120);
+        for c in random_circles(4, 60) {
+            reference.draw_circle(&c);
+        }
+
+        let optimizer = Optimizer::with_reference(circles.clone(), reference, BLACK).unwrap();
+        let refit = optimizer.refit_colors();
+
+        assert!(optimizer.error_of(&refit) <= optimizer.error_of(&circles));
+        for (a, b) in circles.iter().zip(refit.iter()) {
+            assert_eq!((a.x, a.y, a.radius), (b.x, b.y, b.radius));
+        }
+    }
+
+    #[test]
+    fn rejects_a_reference_smaller_than_the_build() {
+        let circles = random_circles(3, 60);
+        let result = Optimizer::with_reference(circles, Canvas::new(80, 60), BLACK);
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn keeps_dots_that_only_show_against_the_background() {
+        let black = Circle::new(20, 20, 3, BLACK);
+        let white = Rgba([255, 255, 255, 255]);
+        let circles = vec![black, Circle::new(40, 40, 3, BLACK)];
+
+        let optimizer = Optimizer::new(circles.clone(), white);
+        assert_eq!(optimizer.precise_prune(), circles);
+        assert_eq!(optimizer.parallel_prune(), circles);
+    }
+
+    #[test]
+    fn removes_covered_circles() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let circles = vec![
+            Circle::new(50, 50, 10, red),
+            Circle::new(52, 50, 30, blue),
+            Circle::new(80, 80, 20, red),
+        ];
+
+        let pruned = Optimizer::new(circles.clone(), BLACK).precise_prune();
+        assert_eq!(pruned, circles[1..].to_vec());
+    }
+
+    #[test]
+    fn removes_circles_hidden_by_matching_colors() {
+        // the second circle is drawn over an identical, larger one and
+        // changes nothing; the first is still visible and must stay
+        let red = Rgba([255, 0, 0, 255]);
+        let circles = vec![
+            Circle::new(40, 40, 30, red),
+            Circle::new(40, 40, 10, red),
+            Circle::new(90, 90, 5, red),
+        ];
+
+        let pruned = Optimizer::new(circles.clone(), BLACK).precise_prune();
+        assert_eq!(pruned, vec![circles[0], circles[2]]);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::Format;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt::Display;
+
+/// Where a command reports to: messages for people on stderr (the default),
+/// or JSON lines on stdout for other programs, with the messages for people
+/// turned off. It's handed to each command with `with_output`.
+///
+/// Each line is an object with an `"event"` name, and each event has its own
+/// fields:
+///
+/// - `config`: `command`, and the `config` it's about to run with
+/// - `status`: a `message` about how a long step is going
+/// - `warning`: a `message` about something that didn't stop the command
+/// - `progress`, `radius` and `finished`: a build's `stats` (see
+///   `observer::stats_json`), every so often, at each radius change and at
+///   the end
+/// - `parts`: a grid build's bill of materials
+/// - `result`: what the command found or made, with the `outputs` written
+///   (for a batch, under each of its `images`)
+/// - `error`: the `message` the command failed with, instead of a result
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Output {
+    format: Format,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Self {
+        Self { format }
+    }
+
+    pub fn is_json(self) -> bool {
+        self.format == Format::Json
+    }
+
+    /// Writes an event as a line of JSON on stdout, in JSON mode only.
+    /// `fields` is an object; `event` is added to it as the event's name.
+    pub fn emit(self, event: &str, fields: Value) {
+        if !self.is_json() {
+            return;
+        }
+
+        let mut line = json!({ "event": event });
+        if let (Value::Object(line), Value::Object(fields)) = (&mut line, fields) {
+            line.extend(fields);
+        }
+        println!("{}", line);
+    }
+
+    /// Notes how a long step is going: a line on stderr, or a "status" event
+    pub fn status(self, message: impl Display) {
+        if self.is_json() {
+            self.emit("status", json!({ "message": message.to_string() }));
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Something went wrong, but not badly enough to stop: a line on stderr,
+    /// or a "warning" event
+    pub fn warning(self, message: impl Display) {
+        if self.is_json() {
+            self.emit("warning", json!({ "message": message.to_string() }));
+        } else {
+            eprintln!("Warning: {}", message);
+        }
+    }
+
+    /// Prints a command's report: its text for people on stdout, or a "result"
+    /// event of the report's fields and the files written
+    pub fn report<R: Serialize>(
+        self,
+        report: &R,
+        text: impl FnOnce(&R) -> String,
+        outputs: &[&String],
+    ) {
+        if self.is_json() {
+            let mut fields = value(report);
+            fields["outputs"] = json!(outputs);
+            self.emit("result", fields);
+        } else {
+            print!("{}", text(report));
+        }
+    }
+}
+
+/// Converts to JSON by way of JSON text, which writes f32s at their shortest
+/// (0.1, not the 0.10000000149011612 `serde_json::to_value` makes of them)
+pub fn value(value: &impl Serialize) -> Value {
+    serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_f32s_short() {
+        assert_eq!(value(&0.1f32).to_string(), "0.1");
+        assert_eq!(value(&[0.25f32, 0.9]).to_string(), "[0.25,0.9]");
+        assert_ne!(json!(0.1f32).to_string(), "0.1");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{Canvas, Error};
+use image::Rgba;
+
+/// Upper bound on the pixels sampled when quantizing a reference image
+const MAX_SAMPLES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub color: Rgba<u8>,
+    pub name: String,
+}
+
+/// A fixed set of colors that shapes are constrained to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub entries: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<Rgba<u8>>) -> Self {
+        let entries = colors
+            .into_iter()
+            .map(|color| PaletteEntry {
+                color,
+                name: String::new(),
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    /// Parses a palette spec: a path ending in .gpl is read as a GIMP palette,
+    /// "auto N" quantizes the reference image down to N colors, and anything
+    /// else is taken as a list of hex colors separated by commas or spaces.
+    ///
+    /// Fails if the file can't be read, or the spec can't be parsed. There are
+    /// no colors to quantize in an empty image, so "auto N" fails on one.
+    pub fn parse(spec: &str, reference: &Canvas) -> crate::Result<Self> {
+        let spec = spec.trim();
+
+        // checked first, so a palette file named "autumn.gpl" is still a file
+        if spec.to_lowercase().ends_with(".gpl") {
+            let contents = std::fs::read_to_string(spec).map_err(|e| Error::io(spec, e))?;
+            return Self::parse_gpl(&contents)
+                .map_err(|e| Error::Config(format!("{}: {}", spec, e)));
+        }
+
+        if let Some(count) = Self::auto_size(spec) {
+            let count: usize = count
+                .parse()
+                .map_err(|_| Error::Config(format!("Invalid palette size in '{}'", spec)))?;
+            if count == 0 {
+                return Err(Error::Config("Palette size must be at least 1".to_owned()));
+            }
+            if reference.width() == 0 || reference.height() == 0 {
+                return Err(Error::Config(format!(
+                    "'{}' needs an image with pixels to pick colors from",
+                    spec
+                )));
+            }
+            return Ok(Self::new(Self::median_cut(reference, count)));
+        }
+
+        Self::parse_hex_list(spec).map_err(Error::Config)
+    }
+
+    // the N of "auto N", if the spec is one
+    fn auto_size(spec: &str) -> Option<&str> {
+        let rest = spec.strip_prefix("auto")?;
+        (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim())
+    }
+
+    pub fn parse_hex_list(list: &str) -> Result<Self, String> {
+        let colors = list
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(Self::parse_hex)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if colors.is_empty() {
+            return Err("Palette has no colors".to_owned());
+        }
+
+        Ok(Self::new(colors))
+    }
+
+    pub fn parse_hex(hex: &str) -> Result<Rgba<u8>, String> {
+        let digits = hex.trim().trim_start_matches('#');
+        let channel = |i: usize| {
+            digits
+                .get(i..i + 2)
+                .and_then(|d| u8::from_str_radix(d, 16).ok())
+                .ok_or_else(|| format!("Invalid hex color '{}'", hex))
+        };
+
+        if digits.len() != 6 {
+            return Err(format!("Invalid hex color '{}'", hex));
+        }
+
+        Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+    }
+
+    pub fn hex(color: Rgba<u8>) -> String {
+        format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+    }
+
+    /// Reads a GIMP palette: a "GIMP Palette" header, optional Name/Columns
+    /// lines and comments, then one "R G B [name]" line per color.
+    pub fn parse_gpl(contents: &str) -> Result<Self, String> {
+        let mut lines = contents.lines().enumerate();
+
+        match lines.next() {
+            Some((_, header)) if header.trim() == "GIMP Palette" => {}
+            _ => return Err("Missing 'GIMP Palette' header".to_owned()),
+        }
+
+        let mut entries = vec![];
+        for (number, line) in lines {
+            let line = line.trim();
+            if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:")
+            {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let mut channel = || {
+                fields
+                    .next()
+                    .and_then(|f| f.parse::<u8>().ok())
+                    .ok_or_else(|| format!("Invalid color on line {}: '{}'", number + 1, line))
+            };
+            let color = Rgba([channel()?, channel()?, channel()?, 255]);
+            let name = fields.collect::<Vec<_>>().join(" ");
+
+            entries.push(PaletteEntry { color, name });
+        }
+
+        if entries.is_empty() {
+            return Err("Palette has no colors".to_owned());
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// The palette color closest to the given color, by the same per-channel
+    /// absolute difference used to score shapes.
+    pub fn nearest(&self, color: Rgba<u8>) -> Rgba<u8> {
+        self.entries[self.nearest_index(color)].color
+    }
+
+    pub fn nearest_index(&self, color: Rgba<u8>) -> usize {
+        self.entries
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, e)| Canvas::pixel_delta(e.color, color))
+            .map(|(i, _)| i)
+            .unwrap_or_default()
+    }
+
+    /// Space separated hex colors, as recorded in .smt metadata
+    pub fn to_hex_list(&self) -> String {
+        self.entries
+            .iter()
+            .map(|e| Self::hex(e.color))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Quantizes the canvas to (at most) `count` colors by median cut: keep
+    /// splitting the box of sampled pixels with the widest channel range at
+    /// its median, then average each box.
+    fn median_cut(canvas: &Canvas, count: usize) -> Vec<Rgba<u8>> {
+        let bytes = canvas.img.as_bytes();
+        let pixel_count = bytes.len() / 4;
+        let stride = (pixel_count / MAX_SAMPLES).max(1);
+
+        let samples: Vec<[u8; 3]> = (0..pixel_count)
+            .step_by(stride)
+            .map(|i| [bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2]])
+            .collect();
+
+        let mut boxes = vec![samples];
+        while boxes.len() < count {
+            let (index, channel, range) = boxes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    let (channel, range) = Self::widest_channel(b);
+                    (i, channel, range)
+                })
+                .max_by_key(|(_, _, range)| *range)
+                .unwrap();
+
+            // every box is a single color; nothing left to split
+            if range == 0 {
+                break;
+            }
+
+            let mut lower = boxes.swap_remove(index);
+            lower.sort_unstable_by_key(|p| p[channel]);
+            let upper = lower.split_off(lower.len() / 2);
+            boxes.push(lower);
+            boxes.push(upper);
+        }
+
+        boxes
+            .iter()
+            .filter(|b| !b.is_empty())
+            .map(|b| {
+                let mut sums = [0usize; 3];
+                for p in b {
+                    for c in 0..3 {
+                        sums[c] += p[c] as usize;
+                    }
+                }
+                let n = b.len();
+                Rgba([
+                    (sums[0] / n) as u8,
+                    (sums[1] / n) as u8,
+                    (sums[2] / n) as u8,
+                    255,
+                ])
+            })
+            .collect()
+    }
+
+    // the channel with the largest spread of values, and that spread
+    fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+        (0..3)
+            .map(|c| {
+                let min = pixels.iter().map(|p| p[c]).min().unwrap_or_default();
+                let max = pixels.iter().map(|p| p[c]).max().unwrap_or_default();
+                (c, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Circle;
+
+    #[test]
+    fn parses_hex_lists() {
+        let palette = Palette::parse_hex_list("#ff0000, 00ff00 #0000FF").unwrap();
+        assert_eq!(palette.to_hex_list(), "#ff0000 #00ff00 #0000ff");
+
+        assert!(Palette::parse_hex_list("#ff00").is_err());
+        assert!(Palette::parse_hex_list("#gg0000").is_err());
+        assert!(Palette::parse_hex_list(" ").is_err());
+    }
+
+    #[test]
+    fn parses_gpl() {
+        let gpl = "GIMP Palette\nName: Beads\nColumns: 2\n#\n255   0   0\tBright Red\n  0   0   0\tBlack\n";
+        let palette = Palette::parse_gpl(gpl).unwrap();
+
+        assert_eq!(palette.entries.len(), 2);
+        assert_eq!(palette.entries[0].color, Rgba([255, 0, 0, 255]));
+        assert_eq!(palette.entries[0].name, "Bright Red");
+
+        assert!(Palette::parse_gpl("255 0 0\n").is_err());
+        assert!(Palette::parse_gpl("GIMP Palette\n255 0\n").is_err());
+    }
+
+    #[test]
+    fn picks_nearest_color() {
+        let palette = Palette::parse_hex_list("#000000 #ffffff #ff0000").unwrap();
+
+        assert_eq!(
+            palette.nearest(Rgba([30, 20, 10, 255])),
+            Rgba([0, 0, 0, 255])
+        );
+        assert_eq!(
+            palette.nearest(Rgba([200, 40, 30, 255])),
+            Rgba([255, 0, 0, 255])
+        );
+        assert_eq!(
+            palette.nearest(Rgba([220, 230, 210, 255])),
+            Rgba([255, 255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn quantizes_automatically() {
+        let mut canvas = Canvas::new(60, 60);
+        canvas.draw_circle(&Circle::new(15, 15, 10, Rgba([250, 10, 10, 255])));
+        canvas.draw_circle(&Circle::new(45, 45, 10, Rgba([10, 10, 250, 255])));
+
+        let palette = Palette::parse("auto 3", &canvas).unwrap();
+        assert_eq!(palette.entries.len(), 3);
+
+        // a single color image can't be split any further
+        let palette = Palette::parse("auto 8", &Canvas::new(10, 10)).unwrap();
+        assert_eq!(palette.entries.len(), 1);
+
+        assert!(Palette::parse("auto", &canvas).is_err());
+        assert!(Palette::parse("auto 0", &canvas).is_err());
+        assert!(Palette::parse("auto 4", &Canvas::new(0, 0)).is_err());
+    }
+
+    #[test]
+    fn reads_files_whose_names_start_with_auto() {
+        let canvas = Canvas::new(10, 10);
+        for spec in ["autumn.gpl", "auto 4.gpl"] {
+            let error = Palette::parse(spec, &canvas).unwrap_err();
+            assert!(matches!(error, Error::Io { .. }), "{}: {}", spec, error);
+            assert!(error.to_string().starts_with(spec), "{}", error);
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use crate::Canvas;
-use rand::Rng;
+use crate::{halftone, Canvas};
+use rand::{seq::SliceRandom, Rng};
 
 /// Picks a random point on the Canvas
 pub struct RandomPointSelector {
@@ -27,3 +27,36 @@ impl Iterator for RandomPointSelector {
         Some((x, y))
     }
 }
+
+/// Visits the cell centers of a halftone screen over the Canvas in a random
+/// order, each once per pass
+pub struct ScreenPointSelector {
+    points: Vec<(u32, u32)>,
+    next: usize,
+}
+
+impl ScreenPointSelector {
+    pub fn new(canvas: &Canvas, pitch: u32, angle: f32) -> Self {
+        Self {
+            points: halftone::screen(canvas.width(), canvas.height(), pitch, angle),
+            next: 0,
+        }
+    }
+}
+
+impl Iterator for ScreenPointSelector {
+    type Item = (u32, u32);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        if self.next == 0 {
+            self.points.shuffle(&mut rand::thread_rng());
+        }
+        let point = self.points[self.next];
+        self.next = (self.next + 1) % self.points.len();
+
+        Some(point)
+    }
+}
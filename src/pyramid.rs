@@ -0,0 +1,121 @@
+use crate::{Canvas, Circle};
+use image::imageops::FilterType;
+
+/// Smallest radius (in level pixels) that is still evaluated on a coarse level
+const MIN_LEVEL_RADIUS: u32 = 16;
+
+/// A downscaled copy of the reference and working canvases. Coordinates on a
+/// level are full-resolution coordinates shifted right by `shift`.
+pub struct Level {
+    pub shift: u32,
+    pub reference: Canvas,
+    pub current: Canvas,
+}
+
+impl Level {
+    pub fn new(reference: &Canvas, current: &Canvas, shift: u32) -> Self {
+        Self {
+            shift,
+            reference: Self::downscale(reference, shift),
+            current: Self::downscale(current, shift),
+        }
+    }
+
+    fn downscale(canvas: &Canvas, shift: u32) -> Canvas {
+        let width = (canvas.width() >> shift).max(1);
+        let height = (canvas.height() >> shift).max(1);
+        let img = canvas.img.resize_exact(width, height, FilterType::Triangle);
+
+        Canvas {
+            center_x: (width as i32) / 2,
+            center_y: (height as i32) / 2,
+            img,
+        }
+    }
+
+    /// Maps a full-resolution circle onto this level
+    pub fn scale_circle(&self, circle: &Circle) -> Circle {
+        let x = (circle.x >> self.shift).min(self.current.width() - 1);
+        let y = (circle.y >> self.shift).min(self.current.height() - 1);
+
+        Circle {
+            x,
+            y,
+            radius: circle.radius >> self.shift,
+            ..*circle
+        }
+    }
+}
+
+/// Coarse-to-fine levels used to evaluate large shapes on downscaled images,
+/// dropping to finer levels as the radius shrinks. Level 0 (full resolution)
+/// is not stored here; it's the builder's own reference and current canvases.
+pub struct Pyramid {
+    levels: Vec<Level>,
+}
+
+impl Pyramid {
+    /// Builds `count - 1` coarse levels, each half the size of the one before
+    pub fn new(reference: &Canvas, current: &Canvas, count: usize) -> Self {
+        let levels = (1..count.max(1) as u32)
+            .map(|shift| Level::new(reference, current, shift))
+            .collect();
+
+        Self { levels }
+    }
+
+    /// Index of the coarsest level that keeps the given full-resolution radius
+    /// at or above MIN_LEVEL_RADIUS, or None if full resolution should be used.
+    pub fn level_for(&self, radius: u32) -> Option<usize> {
+        self.levels
+            .iter()
+            .rposition(|l| (radius >> l.shift) >= MIN_LEVEL_RADIUS)
+    }
+
+    pub fn level(&self, index: usize) -> &Level {
+        &self.levels[index]
+    }
+
+    /// Draws a full-resolution circle onto every coarse level
+    pub fn draw_circle(&mut self, circle: &Circle) {
+        for level in self.levels.iter_mut() {
+            let scaled = level.scale_circle(circle);
+            level.current.draw_circle(&scaled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_coarsest_usable_level() {
+        let reference = Canvas::new(256, 256);
+        let pyramid = Pyramid::new(&reference, &reference, 4);
+
+        assert_eq!(pyramid.level_for(200), Some(2)); // 200 >> 3 = 25
+        assert_eq!(pyramid.level_for(64), Some(1)); // 64 >> 2 = 16
+        assert_eq!(pyramid.level_for(32), Some(0)); // 32 >> 1 = 16
+        assert_eq!(pyramid.level_for(31), None);
+    }
+
+    #[test]
+    fn single_level_is_disabled() {
+        let reference = Canvas::new(64, 64);
+        let pyramid = Pyramid::new(&reference, &reference, 1);
+
+        assert_eq!(pyramid.level_for(500), None);
+    }
+
+    #[test]
+    fn scales_circles() {
+        let reference = Canvas::new(100, 100);
+        let pyramid = Pyramid::new(&reference, &reference, 3);
+        let circle = Circle::new(99, 40, 80, image::Rgba([1, 2, 3, 255]));
+
+        let scaled = pyramid.level(1).scale_circle(&circle);
+        assert_eq!((scaled.x, scaled.y, scaled.radius), (24, 10, 20));
+        assert_eq!((scaled.r, scaled.g, scaled.b), (1, 2, 3));
+    }
+}
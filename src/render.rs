@@ -1,11 +1,24 @@
-use crate::{optimizer::Optimizer, Canvas, Circle, RenderConfig};
-use csv::Reader;
+mod dxf;
+mod pdf;
+mod plotter;
+
+use crate::{
+    color_transform::ColorTransform, halftone, id_buffer::IdBuffer, metrics, optimizer::Optimizer,
+    output::Output, palette::Palette, shape_list::ShapeList, Canvas, Circle, Error, RenderConfig,
+    Result,
+};
 use image::Rgba;
-use std::io::Write;
+use serde_json::json;
 
 pub struct Render {
     config: RenderConfig,
     circles: Vec<Circle>,
+    // the palette the shapes were built with, if one was recorded
+    palette: Option<Palette>,
+    background: Rgba<u8>,
+    // for CMYK halftones, the plate of each circle
+    plates: Option<Vec<usize>>,
+    output: Output,
 }
 
 impl Render {
@@ -22,7 +35,7 @@ impl Render {
         max
     }
 
-    fn image_height(circles: &[Circle]) -> u32 {
+    pub fn image_height(circles: &[Circle]) -> u32 {
         let mut max = 0;
 
         for c in circles {
@@ -35,42 +48,400 @@ impl Render {
         max
     }
 
-    pub fn new(config: RenderConfig) -> Self {
-        let mut csv = Reader::from_path(&config.input).unwrap();
-        let mut circles = vec![];
+    pub fn new(config: RenderConfig) -> Result<Self> {
+        config.validate()?;
+        let shapes = ShapeList::read(&config.input)?;
+        Ok(Self {
+            config,
+            background: shapes.background(),
+            plates: shapes.plates,
+            circles: shapes.circles,
+            palette: shapes.palette,
+            output: Output::default(),
+        })
+    }
 
-        for line in csv.deserialize::<Circle>() {
-            match line {
-                Err(e) => {
-                    panic!("Error reading {}: {}", config.input, e);
-                }
-                Ok(circle) => {
-                    circles.push(circle);
-                }
-            }
+    /// Where progress and the result are reported
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Color transforms requested on the command line, in the order they're
+    /// applied: palette remap, gradient map, hue shift, grayscale, invert.
+    fn color_transforms(&self) -> Result<Vec<ColorTransform>> {
+        let mut transforms = vec![];
+
+        if let Some(spec) = &self.config.remap {
+            // "auto N" quantizes the render itself
+            let palette = Palette::parse(spec, &Self::render_raster(&self.circles))?;
+            transforms.push(ColorTransform::Remap {
+                palette,
+                source: self.palette.clone(),
+            });
+        }
+
+        if let Some(stops) = &self.config.gradient_map {
+            let stops = Palette::parse_hex_list(stops).map_err(Error::Config)?;
+            transforms.push(ColorTransform::GradientMap(
+                stops.entries.iter().map(|e| e.color).collect(),
+            ));
+        }
+
+        if let Some(degrees) = self.config.hue_shift {
+            transforms.push(ColorTransform::HueShift(degrees));
+        }
+
+        if self.config.grayscale {
+            transforms.push(ColorTransform::Grayscale);
+        }
+
+        if self.config.invert {
+            transforms.push(ColorTransform::Invert);
         }
 
-        Self { config, circles }
+        Ok(transforms)
     }
 
     fn hex_color(circle: &Circle) -> String {
         format!("#{:02x?}{:02x?}{:02x?}", circle.r, circle.g, circle.b)
     }
 
-    pub fn run(&self) {
-        let optimizer = Optimizer::new(self.circles.clone());
-        let pruned_circles = optimizer.parallel_prune();
+    pub fn run(&self) -> Result<()> {
+        if let Some(plates) = &self.plates {
+            return self.run_separations(plates);
+        }
+
+        // visibility is a property of the file, so it's measured before pruning
+        // takes the hidden shapes away
+        if let Some(path) = &self.config.ids {
+            let ids = Self::render_ids(&self.circles);
+            let hidden = ids.hidden_shapes(self.circles.len());
+            self.output.status(format!(
+                "{} of {} circles are fully hidden",
+                hidden.len(),
+                self.circles.len()
+            ));
+            ids.false_color().save(path)?;
+        }
+
+        let optimizer =
+            Optimizer::new(self.circles.clone(), self.background).with_output(self.output);
+        let mut pruned_circles = if self.config.precise {
+            optimizer.precise_prune()
+        } else {
+            optimizer.parallel_prune()
+        };
+
+        let reference = match &self.config.refit {
+            Some(path) => Some(Canvas::open(path)?),
+            None => None,
+        };
+        if let Some(reference) = &reference {
+            pruned_circles =
+                Optimizer::with_reference(pruned_circles, reference.clone(), self.background)?
+                    .with_output(self.output)
+                    .refit_colors();
+        }
+
+        // recolor shapes and background alike, so every output matches
+        let transforms = self.color_transforms()?;
+        let circles = ColorTransform::apply_all(&transforms, &pruned_circles);
+        let background = ColorTransform::apply_color(&transforms, self.background);
+
+        // plotter pens are settled before anything is written
+        let plotting = self.config.plotter_svg.is_some()
+            || self.config.hpgl.is_some()
+            || self.config.gcode.is_some();
+        let pens = if plotting {
+            Some(self.pens(&circles, &transforms)?)
+        } else {
+            None
+        };
+
+        if let Some(path) = &self.config.svg {
+            Self::svg_to_file(&circles, background, path)?;
+        }
+
+        if let Some(path) = &self.config.png {
+            Self::png_to_file(&circles, background, path)?;
+        }
+
+        if let Some(pens) = &pens {
+            self.plot(&circles, background, pens)?;
+        }
+
+        if let Some(path) = &self.config.dxf {
+            // recolored shapes no longer line up with the recorded palette
+            let palette = self.palette.as_ref().filter(|_| transforms.is_empty());
+            let scale = self.config.physical_width / Self::image_width(&circles).max(1) as f32;
+            let dxf = dxf::render_dxf(
+                &circles,
+                palette,
+                Self::image_height(&circles),
+                scale,
+                self.config.kerf,
+            );
+            std::fs::write(path, dxf).map_err(|e| Error::io(path, e))?;
+        }
+
+        if self.output.is_json() {
+            // against the refit reference, or else against every shape in the
+            // file, which is all pruning could have changed
+            let (expected, measured_against) = match reference {
+                Some(reference) => (reference, "reference"),
+                None => {
+                    let unpruned = ColorTransform::apply_all(&transforms, &self.circles);
+                    let width = Self::image_width(&unpruned);
+                    let height = Self::image_height(&unpruned);
+                    let expected = Self::render_raster_on(&unpruned, width, height, background);
+                    (expected, "unpruned")
+                }
+            };
+            let render =
+                Self::render_raster_on(&circles, expected.width(), expected.height(), background);
+            let psnr = metrics::psnr(&expected, &render);
+            let ssim = metrics::ssim(&expected, &render);
+
+            self.output.emit(
+                "result",
+                json!({
+                    "input_shapes": self.circles.len(),
+                    "shapes": circles.len(),
+                    "psnr": psnr,
+                    "ssim": ssim,
+                    "measured_against": measured_against,
+                    "outputs": self.outputs(),
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Every file the render writes, in the order it writes them
+    fn outputs(&self) -> Vec<String> {
+        let config = &self.config;
+        if self.plates.is_some() {
+            let plates = config.plates.iter().flat_map(|prefix| {
+                halftone::PLATES.iter().flat_map(move |plate| {
+                    ["svg", "pdf"]
+                        .map(|extension| format!("{}-{}.{}", prefix, plate.name, extension))
+                })
+            });
+            return [&config.svg, &config.png]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .chain(plates)
+                .collect();
+        }
+
+        [
+            &config.ids,
+            &config.svg,
+            &config.png,
+            &config.plotter_svg,
+            &config.hpgl,
+            &config.gcode,
+            &config.dxf,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+
+    /// The pens to plot with: `--pens` if given, otherwise the palette the
+    /// file was built with, recolored like the shapes. A plotter has a pen per
+    /// color, so a file with neither can't be plotted.
+    fn pens(&self, circles: &[Circle], transforms: &[ColorTransform]) -> Result<Palette> {
+        match (&self.config.pens, &self.palette) {
+            (Some(spec), _) => {
+                Palette::parse(spec, &Self::render_raster(circles))
+            }
+            (None, Some(palette)) => Ok(Palette::new(
+                palette
+                    .entries
+                    .iter()
+                    .map(|e| ColorTransform::apply_color(transforms, e.color))
+                    .collect(),
+            )),
+            (None, None) => Err(Error::Config(format!(
+                "{}: plotter output needs a pen for each color; pass --pens, or build with --palette",
+                self.config.input
+            ))),
+        }
+    }
+
+    /// Writes the plotter outputs that were asked for, with each shape snapped
+    /// to the nearest pen, or to the paper if that's nearer
+    fn plot(&self, circles: &[Circle], background: Rgba<u8>, pens: &Palette) -> Result<()> {
+        let mut colors: Vec<Rgba<u8>> = pens.entries.iter().map(|e| e.color).collect();
+        colors.push(background);
+        let choices = Palette::new(colors);
+        let circles: Vec<Circle> = circles
+            .iter()
+            .map(|c| {
+                let color = choices.nearest(Rgba([c.r, c.g, c.b, 255]));
+                Circle::new(c.x, c.y, c.radius, color)
+            })
+            .collect();
+
+        let plot = plotter::Plot::new(
+            &circles,
+            background,
+            Self::image_width(&circles),
+            Self::image_height(&circles),
+            self.config.physical_width,
+            self.config.pen_width,
+            self.config.hatch,
+        );
+
+        let strokes: usize = plot.layers.iter().map(|l| l.strokes.len()).sum();
+        self.output.status(format!(
+            "Plotting {} strokes with {} pens, {:.0}mm of travel",
+            strokes,
+            plot.layers.len(),
+            plot.travel()
+        ));
+
+        let outputs = [
+            (&self.config.plotter_svg, plot.to_svg()),
+            (&self.config.hpgl, plot.to_hpgl()),
+            (&self.config.gcode, plot.to_gcode()),
+        ];
+        for (path, contents) in outputs {
+            if let Some(path) = path {
+                std::fs::write(path, contents).map_err(|e| Error::io(path, e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders CMYK halftone plates. Plates overlap by design and composite
+    /// subtractively, so they're neither pruned nor drawn in painter's order;
+    /// color transforms recolor the inks. Outputs that need shapes painted in
+    /// order are refused rather than quietly skipped.
+    fn run_separations(&self, plates: &[usize]) -> Result<()> {
+        let config = &self.config;
+        let unsupported = [
+            ("--precise", config.precise),
+            ("--refit", config.refit.is_some()),
+            ("--ids", config.ids.is_some()),
+            ("--plotter-svg", config.plotter_svg.is_some()),
+            ("--hpgl", config.hpgl.is_some()),
+            ("--gcode", config.gcode.is_some()),
+            ("--dxf", config.dxf.is_some()),
+        ];
+        let unsupported: Vec<&str> = unsupported
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(flag, _)| *flag)
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(Error::Config(format!(
+                "{}: CMYK files can't be rendered with {}",
+                config.input,
+                unsupported.join(", ")
+            )));
+        }
+
+        let transforms = self.color_transforms()?;
+        let plates = halftone::group_plates(&self.circles, plates);
+        let inks = halftone::PLATES.map(|p| ColorTransform::apply_color(&transforms, p.ink));
+        let width = Self::image_width(&self.circles);
+        let height = Self::image_height(&self.circles);
 
         if let Some(path) = &self.config.svg {
-            Self::svg_to_file(&pruned_circles, path);
+            let raw_svg = Self::render_separations_svg(&plates, &inks, width, height);
+            std::fs::write(path, raw_svg).map_err(|e| Error::io(path, e))?;
         }
 
         if let Some(path) = &self.config.png {
-            Self::png_to_file(&pruned_circles, path);
+            halftone::composite(&plates, &inks, width, height).save(path)?;
+        }
+
+        // each plate on its own, in black, as it would be output to film
+        if let Some(prefix) = &self.config.plates {
+            let black = Rgba([0, 0, 0, 255]);
+            for (plate, circles) in halftone::PLATES.iter().zip(plates.iter()) {
+                let circles: Vec<Circle> = circles
+                    .iter()
+                    .map(|c| Circle::new(c.x, c.y, c.radius, black))
+                    .collect();
+
+                let svg_path = format!("{}-{}.svg", prefix, plate.name);
+                let raw_svg = Self::render_separations_svg(
+                    &[circles.clone(), vec![], vec![], vec![]],
+                    &[black; 4],
+                    width,
+                    height,
+                );
+                std::fs::write(&svg_path, raw_svg).map_err(|e| Error::io(&svg_path, e))?;
+
+                let pdf_path = format!("{}-{}.pdf", prefix, plate.name);
+                std::fs::write(&pdf_path, pdf::render_pdf(&circles, width, height))
+                    .map_err(|e| Error::io(&pdf_path, e))?;
+            }
+        }
+
+        // plates aren't pruned or refit, so there's nothing to measure against
+        self.output.emit(
+            "result",
+            json!({
+                "input_shapes": self.circles.len(),
+                "shapes": self.circles.len(),
+                "psnr": null,
+                "ssim": null,
+                "measured_against": null,
+                "outputs": self.outputs(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Plates as SVG groups over white paper, multiplied together
+    pub fn render_separations_svg(
+        plates: &[Vec<Circle>; 4],
+        inks: &[Rgba<u8>; 4],
+        width: u32,
+        height: u32,
+    ) -> String {
+        let mut output = vec![];
+
+        output.push(format!(
+            "<svg id=\"sedimentSvg\" overflow=\"hidden\" viewBox=\"0 0 {} {}\" preserveAspectRatio=\"xMidYMid meet\" xmlns=\"http://www.w3.org/2000/svg\">",
+            width, height
+        ));
+        output.push(format!(
+            "\t<rect width=\"{}\" height=\"{}\" fill=\"#ffffff\" />",
+            width, height
+        ));
+
+        for (circles, ink) in plates.iter().zip(inks.iter()) {
+            if circles.is_empty() {
+                continue;
+            }
+
+            output.push(format!(
+                "\t<g fill=\"{}\" style=\"mix-blend-mode: multiply\">",
+                Palette::hex(*ink)
+            ));
+            for c in circles {
+                output.push(format!(
+                    "\t\t<circle cx=\"{}\" cy=\"{}\" r=\"{}\" />",
+                    c.x, c.y, c.radius
+                ));
+            }
+            output.push("\t</g>".to_owned());
         }
+
+        output.push("</svg>".to_owned());
+        output.join("\n")
     }
 
-    pub fn render_svg(circles: &[Circle]) -> String {
+    pub fn render_svg(circles: &[Circle], background: Rgba<u8>) -> String {
         let mut output = vec![];
         let width = Self::image_width(circles);
         let height = Self::image_height(circles);
@@ -80,6 +451,16 @@ impl Render {
             width, height
         ));
 
+        // black is the page's own background; anything else is drawn
+        if background != Rgba([0, 0, 0, 255]) {
+            output.push(format!(
+                "\t<rect width=\"{}\" height=\"{}\" fill=\"{}\" />",
+                width,
+                height,
+                Palette::hex(background)
+            ));
+        }
+
         for c in circles {
             output.push(format!(
                 "\t<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" />",
@@ -94,10 +475,9 @@ impl Render {
         output.join("\n")
     }
 
-    fn svg_to_file(circles: &[Circle], path: &str) {
-        let mut output_file = std::fs::File::create(path).unwrap();
-        let raw_svg = Self::render_svg(circles);
-        output_file.write_all(raw_svg.as_bytes()).unwrap();
+    fn svg_to_file(circles: &[Circle], background: Rgba<u8>, path: &str) -> Result<()> {
+        let raw_svg = Self::render_svg(circles, background);
+        std::fs::write(path, raw_svg).map_err(|e| Error::io(path, e))
     }
 
     pub fn render_raster(circles: &[Circle]) -> Canvas {
@@ -110,6 +490,44 @@ impl Render {
         output
     }
 
+    /// Renders the circles over a background color onto a canvas of the given
+    /// size, rather than one sized to fit them
+    pub fn render_raster_on(
+        circles: &[Circle],
+        width: u32,
+        height: u32,
+        background: Rgba<u8>,
+    ) -> Canvas {
+        let mut output = Canvas::filled(width, height, background);
+
+        for circle in circles {
+            Self::add_raster_circle(&mut output, circle);
+        }
+
+        output
+    }
+
+    /// Renders a raw file's shapes the way `run` would, unpruned and
+    /// untransformed: CMYK plates composited, anything else painted in order
+    /// over the recorded background
+    pub fn render_shapes_on(shapes: &ShapeList, width: u32, height: u32) -> Canvas {
+        match shapes.split_plates() {
+            Some(plates) => {
+                let inks = halftone::PLATES.map(|p| p.ink);
+                halftone::composite(&plates, &inks, width, height)
+            }
+            None => Self::render_raster_on(&shapes.circles, width, height, shapes.background()),
+        }
+    }
+
+    /// Renders the index of the topmost circle at each pixel, sized like
+    /// render_raster
+    pub fn render_ids(circles: &[Circle]) -> IdBuffer {
+        let width = Self::image_width(circles);
+        let height = Self::image_height(circles);
+        IdBuffer::render(circles, width, height)
+    }
+
     pub fn create_empty_canvas(circles: &[Circle]) -> Canvas {
         let width = Self::image_width(circles);
         let height = Self::image_height(circles);
@@ -127,7 +545,9 @@ impl Render {
         );
     }
 
-    fn png_to_file(circles: &[Circle], path: &str) {
-        Self::render_raster(circles).save(path);
+    fn png_to_file(circles: &[Circle], background: Rgba<u8>, path: &str) -> Result<()> {
+        let width = Self::image_width(circles);
+        let height = Self::image_height(circles);
+        Self::render_raster_on(circles, width, height, background).save(path)
     }
 }
@@ -0,0 +1,133 @@
+use crate::{palette::Palette, Circle};
+use image::Rgba;
+use std::fmt::Write;
+
+/// Smallest radius written, in millimeters; the least that shows at the four
+/// decimals coordinates are written with
+const MIN_RADIUS: f32 = 0.0001;
+
+/// Writes circles as DXF CIRCLE entities, `scale` millimeters to the pixel,
+/// with y flipped so the drawing isn't mirrored. The file is R12, which has no
+/// way to record units: coordinates are millimeters, and whatever imports the
+/// file should be told so. Each color gets its own layer; with a palette,
+/// colors are bucketed to their nearest entry and layers take the entries'
+/// names. `kerf` is the width of the cut: radii grow by half of it so the
+/// parts that drop out come out at full size.
+pub fn render_dxf(
+    circles: &[Circle],
+    palette: Option<&Palette>,
+    image_height: u32,
+    scale: f32,
+    kerf: f32,
+) -> String {
+    let layer_of = |c: &Circle| {
+        let color = Rgba([c.r, c.g, c.b, 255]);
+        match palette {
+            Some(palette) => {
+                let index = palette.nearest_index(color);
+                let entry = &palette.entries[index];
+                if entry.name.is_empty() {
+                    format!("{}_{}", index + 1, layer_name(&Palette::hex(entry.color)))
+                } else {
+                    layer_name(&entry.name)
+                }
+            }
+            None => layer_name(&Palette::hex(color)),
+        }
+    };
+
+    let mut layers: Vec<String> = vec![];
+    for c in circles {
+        let layer = layer_of(c);
+        if !layers.contains(&layer) {
+            layers.push(layer);
+        }
+    }
+
+    let mut output = String::new();
+    let mut group = |code: u32, value: &str| writeln!(output, "{:>3}\n{}", code, value).unwrap();
+
+    group(0, "SECTION");
+    group(2, "HEADER");
+    group(9, "$ACADVER");
+    group(1, "AC1009");
+    group(0, "ENDSEC");
+
+    group(0, "SECTION");
+    group(2, "TABLES");
+    group(0, "TABLE");
+    group(2, "LAYER");
+    group(70, &layers.len().to_string());
+    for layer in &layers {
+        group(0, "LAYER");
+        group(2, layer);
+        group(70, "0");
+        group(62, "7");
+        group(6, "CONTINUOUS");
+    }
+    group(0, "ENDTAB");
+    group(0, "ENDSEC");
+
+    group(0, "SECTION");
+    group(2, "ENTITIES");
+    for c in circles {
+        group(0, "CIRCLE");
+        group(8, &layer_of(c));
+        group(10, &format!("{:.4}", c.x as f32 * scale));
+        group(
+            20,
+            &format!("{:.4}", (image_height as f32 - c.y as f32) * scale),
+        );
+        group(30, "0.0");
+        let radius = c.radius as f32 * scale + kerf / 2.0;
+        group(40, &format!("{:.4}", radius.max(MIN_RADIUS)));
+    }
+    group(0, "ENDSEC");
+    group(0, "EOF");
+
+    output
+}
+
+// layer names are limited to letters, digits, dashes and underscores
+fn layer_name(name: &str) -> String {
+    name.trim_start_matches('#')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_layer_per_palette_entry() {
+        let red = Rgba([250, 0, 0, 255]);
+        let circles = [
+            Circle::new(10, 10, 5, red),
+            Circle::new(30, 10, 5, Rgba([0, 0, 250, 255])),
+            Circle::new(50, 10, 5, red),
+        ];
+        let palette = Palette::parse_gpl("GIMP Palette\n255 0 0 Bright Red\n0 0 255\n").unwrap();
+
+        let dxf = render_dxf(&circles, Some(&palette), 20, 0.5, 0.2);
+        assert_eq!(dxf.matches("CIRCLE").count(), 3);
+        assert_eq!(dxf.matches("\n  8\nBright_Red\n").count(), 2);
+        assert_eq!(dxf.matches("\n  8\n2_0000ff\n").count(), 1);
+
+        // 5px at half a millimeter each, plus half the kerf
+        assert!(dxf.contains(" 20\n5.0000\n 30\n0.0\n 40\n2.6000\n"));
+        // R12 has no units header
+        assert!(!dxf.contains("$INSUNITS"));
+
+        // a point still makes a circle
+        let dot = render_dxf(&[Circle::new(10, 10, 0, red)], None, 20, 0.5, 0.0);
+        assert!(dot.contains(" 40\n0.0001\n"));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::Circle;
+use std::fmt::Write;
+
+/// Control point distance for approximating a quarter circle with a cubic
+/// Bézier curve, as a fraction of the radius
+const KAPPA: f32 = 0.552_284_8;
+
+/// Renders filled circles as a single-page PDF, one point per pixel. PDF puts
+/// the origin at the bottom left, so y coordinates are flipped.
+pub fn render_pdf(circles: &[Circle], width: u32, height: u32) -> Vec<u8> {
+    let mut content = String::new();
+    let mut fill = None;
+
+    for c in circles {
+        let color = (c.r, c.g, c.b);
+        if fill != Some(color) {
+            writeln!(
+                content,
+                "{:.3} {:.3} {:.3} rg",
+                c.r as f32 / 255.0,
+                c.g as f32 / 255.0,
+                c.b as f32 / 255.0
+            )
+            .unwrap();
+            fill = Some(color);
+        }
+
+        let x = c.x as f32;
+        let y = height as f32 - c.y as f32;
+        let r = c.radius as f32;
+        let k = r * KAPPA;
+
+        writeln!(content, "{} {} m", x + r, y).unwrap();
+        writeln!(
+            content,
+            "{} {} {} {} {} {} c",
+            x + r,
+            y + k,
+            x + k,
+            y + r,
+            x,
+            y + r
+        )
+        .unwrap();
+        writeln!(
+            content,
+            "{} {} {} {} {} {} c",
+            x - k,
+            y + r,
+            x - r,
+            y + k,
+            x - r,
+            y
+        )
+        .unwrap();
+        writeln!(
+            content,
+            "{} {} {} {} {} {} c",
+            x - r,
+            y - k,
+            x - k,
+            y - r,
+            x,
+            y - r
+        )
+        .unwrap();
+        writeln!(
+            content,
+            "{} {} {} {} {} {} c",
+            x + k,
+            y - r,
+            x + r,
+            y - k,
+            x + r,
+            y
+        )
+        .unwrap();
+        writeln!(content, "f").unwrap();
+    }
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_owned(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_owned(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R >>",
+            width, height
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ),
+    ];
+
+    let mut output = String::from("%PDF-1.4\n");
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(output.len());
+        writeln!(output, "{} 0 obj\n{}\nendobj", i + 1, object).unwrap();
+    }
+
+    // cross-reference table: a fixed-width entry per object
+    let xref = output.len();
+    writeln!(output, "xref\n0 {}", objects.len() + 1).unwrap();
+    output.push_str("0000000000 65535 f \n");
+    for offset in offsets {
+        writeln!(output, "{:010} 00000 n ", offset).unwrap();
+    }
+    write!(
+        output,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    )
+    .unwrap();
+
+    output.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn writes_a_path_per_circle_with_working_offsets() {
+        let cyan = Rgba([0, 255, 255, 255]);
+        let circles = [
+            Circle::new(10, 10, 4, cyan),
+            Circle::new(20, 10, 4, cyan),
+            Circle::new(30, 30, 2, Rgba([255, 0, 255, 255])),
+        ];
+
+        let pdf = String::from_utf8(render_pdf(&circles, 40, 40)).unwrap();
+        assert!(pdf.starts_with("%PDF-"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("/MediaBox [0 0 40 40]"));
+
+        // a fill per circle, and a color change only where the color changes
+        assert_eq!(pdf.matches("\nf\n").count(), circles.len());
+        assert_eq!(pdf.matches(" rg\n").count(), 2);
+        // flipped: the first circle's rightmost point is 10px off the bottom
+        assert!(pdf.contains("\n14 30 m\n"));
+
+        // the stream is as long as it says
+        let length = pdf.split("/Length ").nth(1).unwrap();
+        let length: usize = length[..length.find(' ').unwrap()].parse().unwrap();
+        let stream = pdf.find("stream\n").unwrap() + "stream\n".len();
+        assert!(pdf[stream + length..].starts_with("endstream"));
+
+        // startxref points at the table, and each entry at its object
+        let startxref = pdf.rsplit("startxref\n").next().unwrap();
+        let xref: usize = startxref.lines().next().unwrap().parse().unwrap();
+        assert!(pdf[xref..].starts_with("xref\n0 5\n"));
+
+        let entries: Vec<&str> = pdf[xref..].lines().skip(3).take(4).collect();
+        for (i, entry) in entries.iter().enumerate() {
+            assert!(entry.ends_with(" 00000 n "), "{:?}", entry);
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(
+                pdf[offset..].starts_with(&format!("{} 0 obj\n", i + 1)),
+                "object {} at {}",
+                i + 1,
+                offset
+            );
+        }
+    }
+}
@@ -0,0 +1,545 @@
+use crate::{id_buffer::IdBuffer, palette::Palette, Circle, Hatch};
+use image::Rgba;
+use std::fmt::Write;
+
+/// HPGL plotter units per millimeter
+const HPGL_UNITS_PER_MM: f32 = 40.0;
+
+/// G-code pen heights and feed rate, in millimeters and mm/minute
+const GCODE_PEN_UP: f32 = 5.0;
+const GCODE_PEN_DOWN: f32 = 0.0;
+const GCODE_FEED_RATE: f32 = 3000.0;
+
+/// Shortest segment used to approximate a curve, as a fraction of the pen
+/// width; curves are split finely enough to look round at that pen width
+const SEGMENT_LENGTH: f32 = 1.0;
+
+/// A pen-down stroke through a series of points, in millimeters
+pub type Stroke = Vec<(f32, f32)>;
+
+/// A circle's center and radius, in millimeters, and its position in the
+/// shape list
+#[derive(Clone, Copy, Debug)]
+struct Shape {
+    x: f32,
+    y: f32,
+    radius: f32,
+    id: usize,
+}
+
+/// Shapes bucketed by their center on a uniform grid, sized so a cell holds
+/// a shape or so on average
+struct ShapeGrid {
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<Shape>>,
+    len: usize,
+}
+
+impl ShapeGrid {
+    fn new(shapes: Vec<Shape>) -> Self {
+        let width = shapes.iter().map(|s| s.x).fold(0.0, f32::max);
+        let height = shapes.iter().map(|s| s.y).fold(0.0, f32::max);
+        let cell_size = ((width * height) / shapes.len().max(1) as f32)
+            .sqrt()
+            .max(f32::EPSILON);
+
+        let columns = (width / cell_size) as usize + 1;
+        let rows = (height / cell_size) as usize + 1;
+        let mut grid = Self {
+            cell_size,
+            columns,
+            rows,
+            cells: vec![vec![]; columns * rows],
+            len: shapes.len(),
+        };
+
+        for shape in shapes {
+            let (column, row) = grid.cell(shape.x, shape.y);
+            grid.cells[row * columns + column].push(shape);
+        }
+
+        grid
+    }
+
+    // cell holding the given point, clamped to the grid
+    fn cell(&self, x: f32, y: f32) -> (usize, usize) {
+        let column = ((x / self.cell_size).max(0.0) as usize).min(self.columns - 1);
+        let row = ((y / self.cell_size).max(0.0) as usize).min(self.rows - 1);
+        (column, row)
+    }
+
+    /// Removes and returns the shape whose center is closest to the point.
+    /// Searches rings of cells outwards from the point's cell, stopping once
+    /// nothing further out could be closer than the best found so far.
+    fn take_nearest(&mut self, (x, y): (f32, f32)) -> Option<Shape> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let (column, row) = self.cell(x, y);
+        let (column, row) = (column as i64, row as i64);
+        let mut best: Option<(f32, usize, usize)> = None;
+
+        for ring in 0..=self.columns.max(self.rows) as i64 {
+            for r in row - ring..=row + ring {
+                for c in column - ring..=column + ring {
+                    let on_ring = (r - row).abs() == ring || (c - column).abs() == ring;
+                    if !on_ring
+                        || r < 0
+                        || c < 0
+                        || r >= self.rows as i64
+                        || c >= self.columns as i64
+                    {
+                        continue;
+                    }
+
+                    let cell = r as usize * self.columns + c as usize;
+                    for (i, s) in self.cells[cell].iter().enumerate() {
+                        let distance = (s.x - x).hypot(s.y - y);
+                        if best.is_none_or(|(d, _, _)| distance < d) {
+                            best = Some((distance, cell, i));
+                        }
+                    }
+                }
+            }
+
+            // any cell on the next ring is at least this far away
+            if let Some((distance, _, _)) = best {
+                if distance <= ring as f32 * self.cell_size {
+                    break;
+                }
+            }
+        }
+
+        let (_, cell, i) = best?;
+        self.len -= 1;
+        Some(self.cells[cell].swap_remove(i))
+    }
+}
+
+/// Everything drawn with one pen
+pub struct Layer {
+    pub color: Rgba<u8>,
+    pub strokes: Vec<Stroke>,
+}
+
+/// Shapes converted to pen strokes at a physical size. Filled circles become
+/// hatching a pen width apart, one layer per color; within a layer shapes are
+/// ordered nearest neighbour first to keep the pen's travel short.
+pub struct Plot {
+    pub width: f32,
+    pub height: f32,
+    pub pen_width: f32,
+    pub layers: Vec<Layer>,
+}
+
+impl Plot {
+    /// Scales an image of the given pixel size to `width` millimeters across.
+    /// Only what shows of each circle in the render is drawn: hidden circles
+    /// are left out, and hatching is cut wherever a circle is covered,
+    /// including by circles the color of the background, which are holes
+    /// rather than ink.
+    pub fn new(
+        circles: &[Circle],
+        background: Rgba<u8>,
+        image_width: u32,
+        image_height: u32,
+        width: f32,
+        pen_width: f32,
+        hatch: Hatch,
+    ) -> Self {
+        let scale = width / image_width.max(1) as f32;
+        let height = image_height as f32 * scale;
+
+        let ids = IdBuffer::render(circles, image_width, image_height);
+        let visible = ids.visible_counts(circles.len());
+
+        // group shapes by color, in order of first appearance
+        let mut groups: Vec<(Rgba<u8>, Vec<Shape>)> = vec![];
+        for (i, c) in circles.iter().enumerate() {
+            let color = Rgba([c.r, c.g, c.b, 255]);
+            if color == background || visible[i] == 0 {
+                continue;
+            }
+
+            let shape = Shape {
+                x: c.x as f32 * scale,
+                y: c.y as f32 * scale,
+                radius: c.radius as f32 * scale,
+                id: i,
+            };
+            match groups.iter_mut().find(|(k, _)| *k == color) {
+                Some((_, shapes)) => shapes.push(shape),
+                None => groups.push((color, vec![shape])),
+            }
+        }
+
+        let layers = groups
+            .into_iter()
+            .map(|(color, shapes)| Layer {
+                color,
+                strokes: Self::nearest_neighbour_order(shapes)
+                    .into_iter()
+                    .flat_map(|s| {
+                        let strokes = match hatch {
+                            Hatch::Concentric => Self::concentric(s.x, s.y, s.radius, pen_width),
+                            Hatch::Spiral => vec![Self::spiral(s.x, s.y, s.radius, pen_width)],
+                        };
+                        strokes
+                            .into_iter()
+                            .flat_map(|stroke| Self::clip(stroke, s.id, &ids, scale))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            pen_width,
+            layers,
+        }
+    }
+
+    /// Greedily visits whichever shape is closest to the last, starting from
+    /// the origin where the pen parks. Shapes are bucketed on a grid, so each
+    /// step searches outwards from the pen through nearby cells only.
+    fn nearest_neighbour_order(shapes: Vec<Shape>) -> Vec<Shape> {
+        let mut grid = ShapeGrid::new(shapes);
+        let mut ordered = Vec::with_capacity(grid.len);
+        let mut position = (0.0, 0.0);
+
+        while let Some(shape) = grid.take_nearest(position) {
+            position = (shape.x, shape.y);
+            ordered.push(shape);
+        }
+
+        ordered
+    }
+
+    /// Splits a stroke into the runs that lie on pixels the shape `id` shows
+    /// in the render; a run of one point is kept as a dot
+    fn clip(stroke: Stroke, id: usize, ids: &IdBuffer, scale: f32) -> Vec<Stroke> {
+        let shows = |(x, y): (f32, f32)| {
+            let x = (x / scale).round();
+            let y = (y / scale).round();
+            x >= 0.0
+                && y >= 0.0
+                && (x as u32) < ids.width()
+                && (y as u32) < ids.height()
+                && ids.top(x as u32, y as u32) == Some(id)
+        };
+
+        let mut runs = vec![];
+        let mut run: Stroke = vec![];
+        for point in stroke {
+            if shows(point) {
+                run.push(point);
+            } else if !run.is_empty() {
+                runs.push(std::mem::take(&mut run));
+            }
+        }
+        if !run.is_empty() {
+            runs.push(run);
+        }
+
+        for run in &mut runs {
+            if run.len() == 1 {
+                run.push(run[0]);
+            }
+        }
+
+        runs
+    }
+
+    /// Rings a pen width apart, outermost first, each keeping the pen's edge
+    /// inside the circle; circles narrower than the pen are a single dot
+    fn concentric(x: f32, y: f32, radius: f32, pen_width: f32) -> Vec<Stroke> {
+        let mut strokes = vec![];
+        let mut ring = radius - pen_width / 2.0;
+
+        while ring > 0.0 {
+            let steps = Self::steps(ring, pen_width);
+            strokes.push(
+                (0..=steps)
+                    .map(|i| {
+                        let angle = i as f32 / steps as f32 * std::f32::consts::TAU;
+                        (x + ring * angle.cos(), y + ring * angle.sin())
+                    })
+                    .collect(),
+            );
+            ring -= pen_width;
+        }
+
+        // whatever the rings didn't reach in the middle
+        strokes.push(vec![(x, y), (x, y)]);
+        strokes
+    }
+
+    /// One continuous stroke spiraling in from the edge to the center, a pen
+    /// width closer with every turn
+    fn spiral(x: f32, y: f32, radius: f32, pen_width: f32) -> Stroke {
+        let outer = radius - pen_width / 2.0;
+        if outer <= 0.0 {
+            return vec![(x, y), (x, y)];
+        }
+
+        let turns = outer / pen_width;
+        let steps = (Self::steps(outer, pen_width) as f32 * turns).ceil() as usize;
+
+        (0..=steps)
+            .map(|i| {
+                let t = i as f32 / steps as f32;
+                let angle = t * turns * std::f32::consts::TAU;
+                let ring = outer * (1.0 - t);
+                (x + ring * angle.cos(), y + ring * angle.sin())
+            })
+            .collect()
+    }
+
+    // segments needed for one turn at the given radius
+    fn steps(radius: f32, pen_width: f32) -> usize {
+        let circumference = std::f32::consts::TAU * radius;
+        ((circumference / (pen_width * SEGMENT_LENGTH)).ceil() as usize).max(8)
+    }
+
+    /// Total pen-up distance, from the origin through every stroke in order
+    pub fn travel(&self) -> f32 {
+        let mut position = (0.0, 0.0);
+        let mut travel = 0.0;
+
+        for stroke in self.layers.iter().flat_map(|l| l.strokes.iter()) {
+            let start = stroke[0];
+            travel += (start.0 - position.0).hypot(start.1 - position.1);
+            position = stroke[stroke.len() - 1];
+        }
+
+        travel
+    }
+
+    /// SVG sized in millimeters, with each pen on its own Inkscape layer as
+    /// AxiDraw and similar tools expect
+    pub fn to_svg(&self) -> String {
+        let mut output = String::new();
+
+        writeln!(
+            output,
+            "<svg width=\"{w}mm\" height=\"{h}mm\" viewBox=\"0 0 {w} {h}\" xmlns=\"http://www.w3.org/2000/svg\" xmlns:inkscape=\"http://www.inkscape.org/namespaces/inkscape\">",
+            w = self.width,
+            h = self.height
+        )
+        .unwrap();
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let hex = Palette::hex(layer.color);
+            writeln!(
+                output,
+                "\t<g inkscape:groupmode=\"layer\" inkscape:label=\"{} {}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\">",
+                i + 1,
+                hex,
+                hex,
+                self.pen_width
+            )
+            .unwrap();
+
+            for stroke in &layer.strokes {
+                let points: Vec<String> = stroke
+                    .iter()
+                    .map(|(x, y)| format!("{:.3},{:.3}", x, y))
+                    .collect();
+                writeln!(output, "\t\t<path d=\"M{}\" />", points.join(" L")).unwrap();
+            }
+
+            writeln!(output, "\t</g>").unwrap();
+        }
+
+        output.push_str("</svg>\n");
+        output
+    }
+
+    /// HPGL with one pen number per layer. HPGL's origin is the bottom left,
+    /// so y is flipped.
+    pub fn to_hpgl(&self) -> String {
+        let mut output = String::from("IN;\n");
+        let unit = |v: f32| (v * HPGL_UNITS_PER_MM).round() as i32;
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            writeln!(output, "SP{};", i + 1).unwrap();
+
+            for stroke in &layer.strokes {
+                let (x, y) = stroke[0];
+                writeln!(output, "PU{},{};", unit(x), unit(self.height - y)).unwrap();
+
+                let points: Vec<String> = stroke[1..]
+                    .iter()
+                    .map(|(x, y)| format!("{},{}", unit(*x), unit(self.height - y)))
+                    .collect();
+                writeln!(output, "PD{};", points.join(",")).unwrap();
+            }
+        }
+
+        output.push_str("PU;SP0;\n");
+        output
+    }
+
+    /// G-code that lifts and lowers the pen on Z, pausing between layers for
+    /// a pen change. Like HPGL, y is flipped so the origin is the bottom left.
+    pub fn to_gcode(&self) -> String {
+        let mut output = String::new();
+        writeln!(output, "G21 ; millimeters").unwrap();
+        writeln!(output, "G90 ; absolute positioning").unwrap();
+        writeln!(output, "G0 Z{:.3}", GCODE_PEN_UP).unwrap();
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            writeln!(output, "; layer {} {}", i + 1, Palette::hex(layer.color)).unwrap();
+            writeln!(output, "M0 ; load pen {}", Palette::hex(layer.color)).unwrap();
+
+            for stroke in &layer.strokes {
+                let (x, y) = stroke[0];
+                writeln!(output, "G0 X{:.3} Y{:.3}", x, self.height - y).unwrap();
+                writeln!(output, "G1 Z{:.3} F{}", GCODE_PEN_DOWN, GCODE_FEED_RATE).unwrap();
+                for (x, y) in &stroke[1..] {
+                    writeln!(output, "G1 X{:.3} Y{:.3}", x, self.height - y).unwrap();
+                }
+                writeln!(output, "G0 Z{:.3}", GCODE_PEN_UP).unwrap();
+            }
+        }
+
+        writeln!(output, "G0 X0 Y0").unwrap();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    #[test]
+    fn hatches_circles_a_pen_width_apart() {
+        // 100px to 100mm, so a 10px circle is 10mm: rings at 9.5, 8.5 ... 0.5
+        let circles = [
+            Circle::new(50, 50, 10, BLACK),
+            Circle::new(90, 90, 10, WHITE),
+        ];
+        let plot = Plot::new(&circles, WHITE, 100, 100, 100.0, 1.0, Hatch::Concentric);
+
+        assert_eq!(plot.layers.len(), 1);
+        assert_eq!(plot.layers[0].strokes.len(), 11);
+
+        let spiral = Plot::new(&circles, WHITE, 100, 100, 100.0, 1.0, Hatch::Spiral);
+        let stroke = &spiral.layers[0].strokes[0];
+        assert_eq!(stroke[0], (59.5, 50.0));
+        assert_eq!(stroke[stroke.len() - 1], (50.0, 50.0));
+    }
+
+    #[test]
+    fn orders_shapes_to_shorten_travel() {
+        let circles: Vec<Circle> = [90, 10, 70, 30, 50]
+            .iter()
+            .map(|x| Circle::new(*x, 10, 1, RED))
+            .collect();
+        let plot = Plot::new(&circles, BLACK, 100, 20, 100.0, 1.0, Hatch::Concentric);
+
+        let starts: Vec<f32> = plot.layers[0]
+            .strokes
+            .iter()
+            .map(|s| s[0].0.round())
+            .collect();
+        assert_eq!(
+            starts,
+            vec![11.0, 10.0, 31.0, 30.0, 51.0, 50.0, 71.0, 70.0, 91.0, 90.0]
+        );
+        assert!(plot.travel() < 100.0);
+    }
+
+    #[test]
+    fn draws_only_what_shows() {
+        // a ring: a white hole on top of a black dot, on white
+        let ring = [
+            Circle::new(50, 50, 20, BLACK),
+            Circle::new(50, 50, 8, WHITE),
+        ];
+        let plot = Plot::new(&ring, WHITE, 100, 100, 100.0, 1.0, Hatch::Concentric);
+        let points: Vec<(f32, f32)> = plot.layers[0].strokes.iter().flatten().copied().collect();
+        assert!(!points.is_empty());
+        assert!(points.iter().all(|(x, y)| (x - 50.0).hypot(y - 50.0) > 7.5));
+
+        // a dot covered by a bigger one draws nothing
+        let covered = [
+            Circle::new(50, 50, 5, BLACK),
+            Circle::new(50, 50, 10, BLACK),
+        ];
+        let alone = Plot::new(&covered[1..], WHITE, 100, 100, 100.0, 1.0, Hatch::Spiral);
+        let plot = Plot::new(&covered, WHITE, 100, 100, 100.0, 1.0, Hatch::Spiral);
+        assert_eq!(plot.layers[0].strokes, alone.layers[0].strokes);
+    }
+
+    fn random_shapes(count: usize) -> Vec<Shape> {
+        let mut rng = rand::thread_rng();
+        (0..count)
+            .map(|id| Shape {
+                x: rng.gen_range(0.0..400.0),
+                y: rng.gen_range(0.0..300.0),
+                radius: 1.0,
+                id,
+            })
+            .collect()
+    }
+
+    // the order a search through every remaining shape gives
+    fn full_search_order(shapes: &[Shape]) -> Vec<usize> {
+        let mut remaining = shapes.to_vec();
+        let mut order = vec![];
+        let mut position = (0.0, 0.0);
+        while !remaining.is_empty() {
+            let distance = |s: &Shape| (s.x - position.0).hypot(s.y - position.1);
+            let nearest = (0..remaining.len())
+                .min_by(|a, b| distance(&remaining[*a]).total_cmp(&distance(&remaining[*b])))
+                .unwrap();
+            let shape = remaining.swap_remove(nearest);
+            position = (shape.x, shape.y);
+            order.push(shape.id);
+        }
+        order
+    }
+
+    fn ids(shapes: Vec<Shape>) -> Vec<usize> {
+        Plot::nearest_neighbour_order(shapes)
+            .iter()
+            .map(|s| s.id)
+            .collect()
+    }
+
+    #[test]
+    fn orders_like_a_full_search() {
+        let shapes = random_shapes(500);
+        assert_eq!(ids(shapes.clone()), full_search_order(&shapes));
+    }
+
+    #[test]
+    fn orders_sparse_plots_like_a_full_search() {
+        // a dense cluster, and a few shapes far from it and each other, so the
+        // search has to look well past the neighbouring buckets
+        let mut shapes = random_shapes(2000);
+        for (i, (x, y)) in [(5000.0, 40.0), (-300.0, 9000.0), (12000.0, 12000.0)]
+            .into_iter()
+            .enumerate()
+        {
+            shapes.push(Shape {
+                x,
+                y,
+                radius: 1.0,
+                id: 2000 + i,
+            });
+        }
+        assert_eq!(ids(shapes.clone()), full_search_order(&shapes));
+    }
+}
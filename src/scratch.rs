@@ -0,0 +1,37 @@
+use std::path::{Path, PathBuf};
+
+/// A path in the temp directory for a test to write to, unique to the test
+/// and the process running it, and removed (file or directory) on drop
+pub struct Scratch {
+    path: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sediment-{}-{}", std::process::id(), name));
+        Self::remove(&path);
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    fn remove(path: &Path) {
+        if path.is_dir() {
+            let _ = std::fs::remove_dir_all(path);
+        } else {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        Self::remove(&self.path);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{halftone, palette::Palette, Circle, Error, Result};
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// a CSV row: a circle, and in CMYK files the plate it belongs to
+#[derive(Serialize, Deserialize)]
+struct Row {
+    x: u32,
+    y: u32,
+    radius: u32,
+    r: u8,
+    g: u8,
+    b: u8,
+    plate: Option<usize>,
+}
+
+/// The contents of a raw (.smt) file: the circles in draw order, plus any
+/// metadata the build recorded about them. Metadata is stored as leading
+/// "# key: value" lines ahead of the CSV rows.
+#[derive(Debug, Clone, Default)]
+pub struct ShapeList {
+    pub circles: Vec<Circle>,
+    pub palette: Option<Palette>,
+    // color the shapes are drawn over; black if not recorded
+    pub background: Option<Rgba<u8>>,
+    // for CMYK halftones, the plate of each circle (0 to 3: cyan, magenta,
+    // yellow, black), in step with `circles`. Plates composite subtractively
+    // rather than painting over each other.
+    pub plates: Option<Vec<usize>>,
+    // width and height of the image the shapes were built from, if recorded
+    pub size: Option<(u32, u32)>,
+}
+
+impl ShapeList {
+    pub fn read(path: &str) -> Result<Self> {
+        let file = std::fs::File::open(path).map_err(|e| Error::io(path, e))?;
+        Self::read_from(file, path)
+    }
+
+    /// Reads shapes from anything readable; `path` only names the source in
+    /// errors
+    pub fn read_from(mut reader: impl Read, path: &str) -> Result<Self> {
+        let mut contents = String::new();
+        reader
+            .read_to_string(&mut contents)
+            .map_err(|e| Error::io(path, e))?;
+        let mut shapes = Self::default();
+
+        // metadata comes first; everything after it is CSV
+        let mut csv_start = 0;
+        let mut metadata_lines = 0;
+        // named palettes (from .gpl files) keep their names on a line of their
+        // own, so that readers that don't know about names still get colors
+        let mut palette_names = None;
+        for line in contents.split_inclusive('\n') {
+            let Some(entry) = line.strip_prefix('#') else {
+                break;
+            };
+            csv_start += line.len();
+            metadata_lines += 1;
+
+            let Some((key, value)) = entry.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            // unknown keys are ignored so that older builds can read newer files
+            match key.trim() {
+                "palette" => {
+                    let palette = Palette::parse_hex_list(value)
+                        .map_err(|e| Error::parse(path, metadata_lines, e))?;
+                    shapes.palette = Some(palette);
+                }
+                "palette-names" => {
+                    let names: Vec<String> = serde_json::from_str(value)
+                        .map_err(|e| Error::parse(path, metadata_lines, e))?;
+                    palette_names = Some((names, metadata_lines));
+                }
+                "background" => {
+                    let color = Palette::parse_hex(value)
+                        .map_err(|e| Error::parse(path, metadata_lines, e))?;
+                    shapes.background = Some(color);
+                }
+                "mode" => shapes.plates = (value == "cmyk").then(Vec::new),
+                "size" => {
+                    let size = value
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .ok_or_else(|| {
+                            Error::parse(path, metadata_lines, format!("Invalid size '{}'", value))
+                        })?;
+                    shapes.size = Some(size);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((names, line)) = palette_names {
+            match &mut shapes.palette {
+                Some(palette) if palette.entries.len() == names.len() => {
+                    for (entry, name) in palette.entries.iter_mut().zip(names) {
+                        entry.name = name;
+                    }
+                }
+                _ => {
+                    return Err(Error::parse(
+                        path,
+                        line,
+                        "palette names don't match the palette's colors",
+                    ))
+                }
+            }
+        }
+
+        let body = contents.get(csv_start..).unwrap_or_default();
+        let mut csv = csv::Reader::from_reader(body.as_bytes());
+        for line in csv.deserialize::<Row>() {
+            // csv counts lines from the header, after the metadata
+            let row = line.map_err(|e| {
+                let line = e.position().map_or(0, |p| p.line() as usize);
+                Error::parse(path, metadata_lines + line, e)
+            })?;
+
+            if let Some(plates) = &mut shapes.plates {
+                match row.plate {
+                    Some(plate) if plate < halftone::PLATES.len() => plates.push(plate),
+                    _ => {
+                        let line = metadata_lines + shapes.circles.len() + 2;
+                        return Err(Error::parse(path, line, "expected a plate from 0 to 3"));
+                    }
+                }
+            }
+            shapes.circles.push(Circle {
+                x: row.x,
+                y: row.y,
+                radius: row.radius,
+                r: row.r,
+                g: row.g,
+                b: row.b,
+            });
+        }
+
+        Ok(shapes)
+    }
+
+    /// The recorded background, or black
+    pub fn background(&self) -> Rgba<u8> {
+        self.background.unwrap_or(Rgba([0, 0, 0, 255]))
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+        self.write_to(file, path)
+    }
+
+    /// Writes shapes to anything writable; `path` only names the destination
+    /// in errors
+    pub fn write_to(&self, mut file: impl Write, path: &str) -> Result<()> {
+        let io_error = |e| Error::io(path, e);
+
+        if self.plates.is_some() {
+            writeln!(file, "# mode: cmyk").map_err(io_error)?;
+        }
+
+        if let Some((width, height)) = self.size {
+            writeln!(file, "# size: {}x{}", width, height).map_err(io_error)?;
+        }
+
+        if let Some(palette) = &self.palette {
+            writeln!(file, "# palette: {}", palette.to_hex_list()).map_err(io_error)?;
+
+            if palette.entries.iter().any(|e| !e.name.is_empty()) {
+                let names: Vec<&str> = palette.entries.iter().map(|e| e.name.as_str()).collect();
+                writeln!(
+                    file,
+                    "# palette-names: {}",
+                    serde_json::to_string(&names).unwrap()
+                )
+                .map_err(io_error)?;
+            }
+        }
+
+        if let Some(background) = self.background {
+            writeln!(file, "# background: {}", Palette::hex(background)).map_err(io_error)?;
+        }
+
+        let mut writer = csv::Writer::from_writer(file);
+        match &self.plates {
+            Some(plates) => {
+                for (c, plate) in self.circles.iter().zip(plates) {
+                    let row = Row {
+                        x: c.x,
+                        y: c.y,
+                        radius: c.radius,
+                        r: c.r,
+                        g: c.g,
+                        b: c.b,
+                        plate: Some(*plate),
+                    };
+                    writer.serialize(row).map_err(|e| io_error(e.into()))?;
+                }
+            }
+            None => {
+                for c in &self.circles {
+                    writer.serialize(c).map_err(|e| io_error(e.into()))?;
+                }
+            }
+        }
+        writer.flush().map_err(io_error)
+    }
+
+    /// For CMYK halftones, the circles of each plate, in plate order
+    pub fn split_plates(&self) -> Option<[Vec<Circle>; 4]> {
+        let plates = self.plates.as_ref()?;
+        Some(halftone::group_plates(&self.circles, plates))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_line_of_a_bad_row() {
+        let contents = "# background: #ffffff\nx,y,radius,r,g,b\n1,2,3,4,5,6\n1,2,three,4,5,6\n";
+
+        match ShapeList::read_from(contents.as_bytes(), "bad-row.smt") {
+            Err(Error::Parse { line, .. }) => assert_eq!(line, 4),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let shapes = ShapeList {
+            circles: vec![Circle::new(4, 5, 6, Rgba([7, 8, 9, 255]))],
+            palette: Some(
+                Palette::parse_gpl("GIMP Palette\n7 8 9\tNight, \"Deep\"\n255 255 255\n").unwrap(),
+            ),
+            background: Some(Rgba([255, 255, 255, 255])),
+            plates: None,
+            size: Some((20, 10)),
+        };
+
+        let mut written = vec![];
+        shapes.write_to(&mut written, "written.smt").unwrap();
+        let read = ShapeList::read_from(written.as_slice(), "written.smt").unwrap();
+
+        assert_eq!(format!("{:?}", read), format!("{:?}", shapes));
+    }
+
+    #[test]
+    fn records_the_plate_of_each_halftone_dot() {
+        // recolored inks no longer say which plate a dot is on
+        let white = Rgba([255, 255, 255, 255]);
+        let shapes = ShapeList {
+            circles: vec![Circle::new(1, 1, 1, white), Circle::new(2, 2, 2, white)],
+            plates: Some(vec![3, 0]),
+            ..Default::default()
+        };
+
+        let mut written = vec![];
+        shapes.write_to(&mut written, "plates.smt").unwrap();
+        let read = ShapeList::read_from(written.as_slice(), "plates.smt").unwrap();
+        assert_eq!(read.plates, Some(vec![3, 0]));
+
+        let missing = "# mode: cmyk\nx,y,radius,r,g,b\n1,2,3,4,5,6\n";
+        match ShapeList::read_from(missing.as_bytes(), "missing.smt") {
+            Err(Error::Parse { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    halftone, metrics, optimizer::Optimizer, output::Output, shape_list::ShapeList, Canvas, Circle,
+    Render, Result, SimplifyConfig,
+};
+use image::Rgba;
+use serde_json::json;
+
+pub struct Simplifier {
+    config: SimplifyConfig,
+    shapes: ShapeList,
+    output: Output,
+}
+
+impl Simplifier {
+    pub fn new(config: SimplifyConfig) -> Result<Self> {
+        let shapes = ShapeList::read(&config.input)?;
+        Ok(Self {
+            config,
+            shapes,
+            output: Output::default(),
+        })
+    }
+
+    /// Where progress and the result are reported
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let reference = match &self.config.reference {
+            Some(path) => Some(Canvas::open(path)?),
+            None => None,
+        };
+
+        let simplified = match &self.shapes.plates {
+            Some(plates) => self.simplify_plates(plates, reference.as_ref())?,
+            None => {
+                // without a reference image, fidelity is measured against the
+                // full render
+                let optimizer = match &reference {
+                    Some(reference) => Optimizer::with_reference(
+                        self.shapes.circles.clone(),
+                        reference.clone(),
+                        self.shapes.background(),
+                    )?,
+                    None => Optimizer::new(self.shapes.circles.clone(), self.shapes.background()),
+                }
+                .with_output(self.output);
+
+                // keep whatever metadata the build recorded
+                ShapeList {
+                    circles: optimizer.simplify(self.config.target, self.config.max_error),
+                    ..self.shapes.clone()
+                }
+            }
+        };
+        simplified.write(&self.config.output)?;
+
+        if self.output.is_json() {
+            let reference = reference.unwrap_or_else(|| {
+                let circles = &self.shapes.circles;
+                Render::render_shapes_on(
+                    &self.shapes,
+                    Render::image_width(circles),
+                    Render::image_height(circles),
+                )
+            });
+            let render =
+                Render::render_shapes_on(&simplified, reference.width(), reference.height());
+
+            self.output.emit(
+                "result",
+                json!({
+                    "input_shapes": self.shapes.circles.len(),
+                    "shapes": simplified.circles.len(),
+                    "psnr": metrics::psnr(&reference, &render),
+                    "outputs": [self.config.output],
+                }),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Halftone plates composite rather than paint over each other, so each is
+    /// simplified on its own, as the black on white film it's printed from
+    /// (against the reference's separation for that plate, if there is one).
+    /// A target is shared out between the plates by their size; an error limit
+    /// applies to each plate.
+    fn simplify_plates(&self, plates: &[usize], reference: Option<&Canvas>) -> Result<ShapeList> {
+        let white = Rgba([255, 255, 255, 255]);
+        let black = Rgba([0, 0, 0, 255]);
+        let total = self.shapes.circles.len().max(1);
+
+        let mut circles = vec![];
+        let mut kept_plates = vec![];
+        let grouped = halftone::group_plates(&self.shapes.circles, plates);
+        for (index, plate) in grouped.iter().enumerate() {
+            let film: Vec<Circle> = plate
+                .iter()
+                .map(|c| Circle::new(c.x, c.y, c.radius, black))
+                .collect();
+            let optimizer = match reference {
+                Some(reference) => {
+                    Optimizer::with_reference(film, halftone::separation(reference, index), white)?
+                }
+                None => Optimizer::new(film, white),
+            }
+            .with_output(self.output);
+
+            let target = self.config.target.map(|t| t * plate.len() / total);
+            let kept = optimizer.simplify(target, self.config.max_error);
+
+            // the kept dots are in their original order; take their colors back
+            let mut originals = plate.iter();
+            for k in kept {
+                let original = originals
+                    .find(|c| (c.x, c.y, c.radius) == (k.x, k.y, k.radius))
+                    .unwrap();
+                circles.push(*original);
+                kept_plates.push(index);
+            }
+        }
+
+        Ok(ShapeList {
+            circles,
+            plates: Some(kept_plates),
+            ..self.shapes.clone()
+        })
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{Circle, Region, Render};
+
+/// Default edge length of a grid cell, in pixels
+pub const DEFAULT_CELL_SIZE: u32 = 32;
+
+/// A uniform grid over circle bounds, for finding the circles that overlap a
+/// region without scanning the whole list. The index stores
+/// positions into a circle list owned by the caller; queries take that list to
+/// run the exact overlap tests, and return positions in ascending (draw) order.
+pub struct SpatialIndex {
+    cell_size: u32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl SpatialIndex {
+    pub fn new(width: u32, height: u32, cell_size: u32) -> Self {
+        let cell_size = cell_size.max(1);
+        let columns = (width / cell_size) as usize + 1;
+        let rows = (height / cell_size) as usize + 1;
+
+        Self {
+            cell_size,
+            columns,
+            rows,
+            cells: vec![vec![]; columns * rows],
+        }
+    }
+
+    /// Builds an index sized to fit the circles, with every circle inserted
+    pub fn from_circles(circles: &[Circle]) -> Self {
+        let width = Render::image_width(circles);
+        let height = Render::image_height(circles);
+        let mut index = Self::new(width, height, DEFAULT_CELL_SIZE);
+
+        for (i, c) in circles.iter().enumerate() {
+            index.insert(i, c);
+        }
+
+        index
+    }
+
+    // range of cells covering the given bounds, clamped to the grid
+    fn cell_range(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        max_x: i64,
+        max_y: i64,
+    ) -> (usize, usize, usize, usize) {
+        let size = self.cell_size as i64;
+        let clamp_column = |v: i64| (v.max(0) / size).min(self.columns as i64 - 1) as usize;
+        let clamp_row = |v: i64| (v.max(0) / size).min(self.rows as i64 - 1) as usize;
+
+        (
+            clamp_column(min_x),
+            clamp_row(min_y),
+            clamp_column(max_x),
+            clamp_row(max_y),
+        )
+    }
+
+    fn circle_cells(&self, circle: &Circle) -> (usize, usize, usize, usize) {
+        let x = circle.x as i64;
+        let y = circle.y as i64;
+        let r = circle.radius as i64;
+        self.cell_range(x - r, y - r, x + r, y + r)
+    }
+
+    /// Adds the circle at position `index` of the caller's list
+    pub fn insert(&mut self, index: usize, circle: &Circle) {
+        let (min_column, min_row, max_column, max_row) = self.circle_cells(circle);
+
+        for row in min_row..=max_row {
+            for column in min_column..=max_column {
+                self.cells[row * self.columns + column].push(index);
+            }
+        }
+    }
+
+    /// Positions of every indexed circle whose cells touch the given bounds.
+    /// This is a superset of the overlapping circles; use the exact queries
+    /// below unless you're doing your own filtering.
+    pub fn candidates(&self, min_x: i64, min_y: i64, max_x: i64, max_y: i64) -> Vec<usize> {
+        let (min_column, min_row, max_column, max_row) =
+            self.cell_range(min_x, min_y, max_x, max_y);
+
+        let mut found = vec![];
+        for row in min_row..=max_row {
+            for column in min_column..=max_column {
+                found.extend_from_slice(&self.cells[row * self.columns + column]);
+            }
+        }
+
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    /// Positions of the circles that overlap the region, in list order
+    pub fn overlapping_region(&self, circles: &[Circle], region: &Region) -> Vec<usize> {
+        self.candidates(
+            region.min_x as i64,
+            region.min_y as i64,
+            region.max_x as i64,
+            region.max_y as i64,
+        )
+        .into_iter()
+        .filter(|i| circles[*i].overlaps_region(region))
+        .collect()
+    }
+
+    /// Positions of the circles that come within `gap` pixels of the given
+    /// circle, in list order
+    pub fn overlapping_circle(&self, circles: &[Circle], circle: &Circle, gap: u32) -> Vec<usize> {
+        // growing the probe by the gap turns "too close" into "overlapping"
+        let probe = Circle {
+            radius: circle.radius + gap,
+            ..*circle
+        };
+        let x = probe.x as i64;
+        let y = probe.y as i64;
+        let r = probe.radius as i64;
+
+        self.candidates(x - r, y - r, x + r, y + r)
+            .into_iter()
+            .filter(|i| circles[*i].overlaps_circle(&probe))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use rand::Rng;
+
+    fn random_circles(count: usize) -> Vec<Circle> {
+        let mut rng = rand::thread_rng();
+        (0..count)
+            .map(|_| {
+                Circle::new(
+                    rng.gen_range(0..400),
+                    rng.gen_range(0..300),
+                    rng.gen_range(1..60),
+                    Rgba([0, 0, 0, 255]),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let circles = random_circles(500);
+        let index = SpatialIndex::from_circles(&circles);
+
+        for probe in random_circles(50) {
+            let region = Region::new(probe.x, probe.y, probe.radius);
+            let expected: Vec<usize> = (0..circles.len())
+                .filter(|i| circles[*i].overlaps_region(&region))
+                .collect();
+            assert_eq!(index.overlapping_region(&circles, &region), expected);
+        }
+    }
+
+    #[test]
+    fn finds_overlapping_circles() {
+        let circles = random_circles(500);
+        let index = SpatialIndex::from_circles(&circles);
+
+        for probe in random_circles(50) {
+            let grown = Circle::new(probe.x, probe.y, probe.radius + 3, Rgba([0, 0, 0, 255]));
+            let expected: Vec<usize> = (0..circles.len())
+                .filter(|i| circles[*i].overlaps_circle(&grown))
+                .collect();
+            assert_eq!(index.overlapping_circle(&circles, &probe, 3), expected);
+        }
+
+        // circles that only touch don't overlap
+        let a = Circle::new(10, 10, 5, Rgba([0, 0, 0, 255]));
+        let b = Circle::new(20, 10, 5, Rgba([0, 0, 0, 255]));
+        let index = SpatialIndex::from_circles(&[a]);
+        assert!(index.overlapping_circle(&[a], &b, 0).is_empty());
+        assert_eq!(index.overlapping_circle(&[a], &b, 1), vec![0]);
+    }
+}
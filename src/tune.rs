@@ -0,0 +1,376 @@
+use crate::{
+    metrics, output::Output, BuildOptions, Builder, Canvas, Error, Result, Search, TuneConfig,
+};
+use image::imageops::FilterType;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use std::fmt::Write;
+use std::time::Instant;
+
+/// Values a grid search tries for each setting; a random search draws from
+/// between the smallest and largest of them
+const SHRINK_THRESHOLDS: [f32; 3] = [0.1, 0.2, 0.4];
+const RADIUS_STEPS: [f32; 3] = [0.05, 0.1, 0.2];
+const ATTEMPT_LIMITS: [usize; 3] = [1000, 3000, 5000];
+const SIMILARITY_THRESHOLDS: [f32; 3] = [0.85, 0.9, 0.95];
+
+/// The build settings `tune` searches over
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub radius_shrink_threshold: f32,
+    pub radius_step: f32,
+    pub radius_attempt_limit: usize,
+    pub similarity_threshold: f32,
+}
+
+impl Settings {
+    /// Every combination of the grid values
+    pub fn grid() -> Vec<Self> {
+        let mut settings = Vec::new();
+        for radius_shrink_threshold in SHRINK_THRESHOLDS {
+            for radius_step in RADIUS_STEPS {
+                for radius_attempt_limit in ATTEMPT_LIMITS {
+                    for similarity_threshold in SIMILARITY_THRESHOLDS {
+                        settings.push(Self {
+                            radius_shrink_threshold,
+                            radius_step,
+                            radius_attempt_limit,
+                            similarity_threshold,
+                        });
+                    }
+                }
+            }
+        }
+        settings
+    }
+
+    /// Settings drawn from the grid's ranges, rounded so they read well in
+    /// a report or config file
+    pub fn random(rng: &mut impl Rng, count: usize) -> Vec<Self> {
+        fn range<T: Copy + PartialOrd>(values: &[T]) -> std::ops::RangeInclusive<T> {
+            values[0]..=values[values.len() - 1]
+        }
+        let hundredths = |value: f32| (value * 100.0).round() / 100.0;
+
+        (0..count)
+            .map(|_| Self {
+                radius_shrink_threshold: hundredths(rng.gen_range(range(&SHRINK_THRESHOLDS))),
+                radius_step: hundredths(rng.gen_range(range(&RADIUS_STEPS))),
+                radius_attempt_limit: rng.gen_range(range(&ATTEMPT_LIMITS)) / 100 * 100,
+                similarity_threshold: hundredths(rng.gen_range(range(&SIMILARITY_THRESHOLDS))),
+            })
+            .collect()
+    }
+
+    /// The options, with these settings in place of their own
+    pub fn apply(&self, options: &BuildOptions) -> BuildOptions {
+        BuildOptions {
+            radius_shrink_threshold: self.radius_shrink_threshold,
+            radius_step: self.radius_step,
+            radius_attempt_limit: self.radius_attempt_limit,
+            similarity_threshold: self.similarity_threshold,
+            ..options.clone()
+        }
+    }
+}
+
+/// One trial build and how it scored against the (downscaled) input
+#[derive(Serialize, Debug)]
+pub struct Trial {
+    pub settings: Settings,
+    pub shapes: usize,
+    pub seconds: f64,
+    pub psnr: f64,
+    pub psnr_per_shape: f64,
+    pub psnr_per_second: f64,
+}
+
+impl Trial {
+    pub fn new(settings: Settings, shapes: usize, seconds: f64, psnr: f64) -> Self {
+        Self {
+            settings,
+            shapes,
+            seconds,
+            psnr,
+            psnr_per_shape: psnr / shapes.max(1) as f64,
+            psnr_per_second: psnr / seconds.max(f64::EPSILON),
+        }
+    }
+
+    // at least as good on every count, and better on one
+    fn dominates(&self, other: &Trial) -> bool {
+        self.psnr >= other.psnr
+            && self.shapes <= other.shapes
+            && self.seconds <= other.seconds
+            && (self.psnr > other.psnr
+                || self.shapes < other.shapes
+                || self.seconds < other.seconds)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    // size of the downscaled input the trials were built from
+    pub width: u32,
+    pub height: u32,
+    pub trials: Vec<Trial>,
+    // indices of the trials no other beats on PSNR, shape count and time all
+    // at once, best PSNR first
+    pub front: Vec<usize>,
+    // index of the trial on the front that balances the three best
+    pub recommended: usize,
+}
+
+impl Report {
+    pub fn new(width: u32, height: u32, trials: Vec<Trial>) -> Self {
+        let front = Self::pareto_front(&trials);
+        let recommended = Self::knee(&trials, &front);
+
+        Self {
+            width,
+            height,
+            trials,
+            front,
+            recommended,
+        }
+    }
+
+    fn pareto_front(trials: &[Trial]) -> Vec<usize> {
+        let mut front: Vec<usize> = (0..trials.len())
+            .filter(|&i| !trials.iter().any(|other| other.dominates(&trials[i])))
+            .collect();
+        front.sort_by(|&a, &b| trials[b].psnr.total_cmp(&trials[a].psnr));
+        front
+    }
+
+    /// The trial on the front closest to an ideal one with the front's best
+    /// PSNR, fewest shapes and shortest time, with each measure scaled to
+    /// the front's range so none of them dominates
+    fn knee(trials: &[Trial], front: &[usize]) -> usize {
+        let measures: [fn(&Trial) -> f64; 3] = [|t| -t.psnr, |t| t.shapes as f64, |t| t.seconds];
+        let ranges = measures.map(|measure| {
+            let values = front.iter().map(|&i| measure(&trials[i]));
+            let min = values.clone().fold(f64::INFINITY, f64::min);
+            let max = values.fold(f64::NEG_INFINITY, f64::max);
+            (min, max)
+        });
+
+        let distance = |trial: &Trial| -> f64 {
+            measures
+                .iter()
+                .zip(ranges)
+                .map(|(measure, (min, max))| {
+                    let shortfall = if max > min {
+                        (measure(trial) - min) / (max - min)
+                    } else {
+                        0.0
+                    };
+                    shortfall * shortfall
+                })
+                .sum()
+        };
+
+        front
+            .iter()
+            .copied()
+            .min_by(|&a, &b| distance(&trials[a]).total_cmp(&distance(&trials[b])))
+            .unwrap_or(0)
+    }
+
+    pub fn recommended(&self) -> &Trial {
+        &self.trials[self.recommended]
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+
+        writeln!(
+            output,
+            "tuned at {}x{} over {} trials; {} on the Pareto front, best PSNR first:\n",
+            self.width,
+            self.height,
+            self.trials.len(),
+            self.front.len()
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "  {:>6}  {:>6}  {:>8}  {:>10}  {:>7}  {:>7}  {:>8}  {:>10}  {:>7}",
+            "shrink",
+            "step",
+            "attempts",
+            "similarity",
+            "shapes",
+            "time",
+            "psnr",
+            "psnr/shape",
+            "psnr/s"
+        )
+        .unwrap();
+
+        for &i in &self.front {
+            let trial = &self.trials[i];
+            let settings = &trial.settings;
+            writeln!(
+                output,
+                "{} {:>6.3}  {:>6.3}  {:>8}  {:>10.3}  {:>7}  {:>6.2}s  {:>8.2}  {:>10.4}  {:>7.2}",
+                if i == self.recommended { "*" } else { " " },
+                settings.radius_shrink_threshold,
+                settings.radius_step,
+                settings.radius_attempt_limit,
+                settings.similarity_threshold,
+                trial.shapes,
+                trial.seconds,
+                trial.psnr,
+                trial.psnr_per_shape,
+                trial.psnr_per_second
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "\n* recommended: the best balance of PSNR, shape count and time"
+        )
+        .unwrap();
+
+        output
+    }
+}
+
+/// The `tune` subcommand: builds a downscaled copy of the input with a range
+/// of settings and reports which trade quality against shapes and time best
+pub struct Tuner {
+    config: TuneConfig,
+    output: Output,
+}
+
+impl Tuner {
+    pub fn new(config: TuneConfig) -> Self {
+        Self {
+            config,
+            output: Output::default(),
+        }
+    }
+
+    /// Where progress and the result are reported
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let (reference, scale) =
+            Self::downscale(Canvas::open(&self.config.input)?, self.config.size);
+        let options = Self::scale_options(&self.config.options, scale);
+
+        let candidates = match self.config.search {
+            Search::Grid => Settings::grid(),
+            Search::Random => {
+                let mut rng = match self.config.seed {
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                    None => StdRng::from_entropy(),
+                };
+                Settings::random(&mut rng, self.config.samples)
+            }
+        };
+        if candidates.is_empty() {
+            return Err(Error::Config("samples must be at least 1".to_owned()));
+        }
+
+        // one at a time, so the timings aren't fighting each other for CPUs
+        let count = candidates.len();
+        let mut trials = Vec::with_capacity(count);
+        for (i, settings) in candidates.into_iter().enumerate() {
+            self.output
+                .status(format!("Trial {}/{}: {:?}", i + 1, count, settings));
+            trials.push(Self::trial(&reference, settings, &options)?);
+        }
+
+        let report = Report::new(reference.width(), reference.height(), trials);
+
+        // the full-size options, so the file is ready to build the input with
+        if let Some(path) = &self.config.output {
+            report
+                .recommended()
+                .settings
+                .apply(&self.config.options)
+                .write(path)?;
+        }
+
+        let outputs: Vec<_> = self.config.output.iter().collect();
+        self.output.report(&report, Report::to_text, &outputs);
+
+        Ok(())
+    }
+
+    fn trial(reference: &Canvas, settings: Settings, options: &BuildOptions) -> Result<Trial> {
+        let start = Instant::now();
+        let mut builder = Builder::new(reference.clone(), settings.apply(options))?;
+        let shapes = builder.run()?;
+        let seconds = start.elapsed().as_secs_f64();
+
+        Ok(Trial::new(
+            settings,
+            shapes.circles.len(),
+            seconds,
+            metrics::psnr(reference, builder.image()),
+        ))
+    }
+
+    /// Shrinks the image until its longer side is at most `size`, returning
+    /// it with the factor it was scaled by
+    fn downscale(canvas: Canvas, size: u32) -> (Canvas, f32) {
+        let longer = canvas.width().max(canvas.height());
+        if longer <= size || size == 0 {
+            return (canvas, 1.0);
+        }
+
+        let scale = size as f32 / longer as f32;
+        let width = ((canvas.width() as f32 * scale).round() as u32).max(1);
+        let height = ((canvas.height() as f32 * scale).round() as u32).max(1);
+        let img = canvas.img.resize_exact(width, height, FilterType::Triangle);
+
+        (Canvas::from_image(img), scale)
+    }
+
+    // distances in pixels shrink with the image
+    fn scale_options(options: &BuildOptions, scale: f32) -> BuildOptions {
+        let scaled = |pixels: u32| (pixels as f32 * scale).round() as u32;
+        let max_radius = scaled(options.max_radius).max(1);
+
+        BuildOptions {
+            max_radius,
+            min_radius: scaled(options.min_radius).clamp(1, max_radius),
+            pitch: scaled(options.pitch).max(1),
+            min_gap: scaled(options.min_gap),
+            ..options.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trial(psnr: f64, shapes: usize, seconds: f64) -> Trial {
+        Trial::new(Settings::grid()[0], shapes, seconds, psnr)
+    }
+
+    #[test]
+    fn recommends_a_balance_from_the_front() {
+        let trials = vec![
+            // best quality, but costly
+            trial(30.0, 4000, 8.0),
+            // cheapest, but poor
+            trial(18.0, 200, 0.5),
+            trial(27.0, 1200, 2.0),
+            // worse than the one before on every count
+            trial(25.0, 1500, 3.0),
+        ];
+        let report = Report::new(100, 100, trials);
+
+        assert_eq!(report.front, vec![0, 2, 1]);
+        assert_eq!(report.recommended, 2);
+    }
+}